@@ -0,0 +1,923 @@
+use cart::region;
+
+const LENGTH_TABLE : [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+const DUTY_TABLE : [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+const TRIANGLE_TABLE : [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15
+];
+
+const DEFAULT_SAMPLE_RATE : u32 = 44100;
+
+// What differs between the NTSC and PAL APUs.  Dendy clones have the
+// NTSC one, run from a slightly faster clock.
+struct Timing {
+    cpu_clock_rate: f64,
+    // Periods in CPU ticks
+    noise_periods: [u16; 16],
+    dmc_rates: [u16; 16],
+    // Frame sequencer steps, in CPU ticks since the sequence started
+    frame_step_1: u32,
+    frame_step_2: u32,
+    frame_step_3: u32,
+    frame_irq_start: u32,
+    frame_step_4: u32,
+    frame_step_5: u32,
+    frame_length_4_step: u32,
+    frame_length_5_step: u32
+}
+
+const NTSC : Timing = Timing {
+    cpu_clock_rate: 1789773.0,
+    noise_periods: [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068],
+    dmc_rates: [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54],
+    frame_step_1: 7457,
+    frame_step_2: 14913,
+    frame_step_3: 22371,
+    frame_irq_start: 29828,
+    frame_step_4: 29829,
+    frame_step_5: 37281,
+    frame_length_4_step: 29830,
+    frame_length_5_step: 37282
+};
+
+const PAL : Timing = Timing {
+    cpu_clock_rate: 1662607.0,
+    noise_periods: [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778],
+    dmc_rates: [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50],
+    frame_step_1: 8313,
+    frame_step_2: 16627,
+    frame_step_3: 24939,
+    frame_irq_start: 33252,
+    frame_step_4: 33253,
+    frame_step_5: 41565,
+    frame_length_4_step: 33254,
+    frame_length_5_step: 41566
+};
+
+const DENDY : Timing = Timing { cpu_clock_rate: 1773448.0, ..NTSC };
+
+struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { start: false, loop_flag: false, constant_volume: false,
+            volume: 0, divider: 0, decay: 0 }
+    }
+
+    fn reg_write(&mut self, data: u8) {
+        self.loop_flag = (data & 0x20) == 0x20;
+        self.constant_volume = (data & 0x10) == 0x10;
+        self.volume = data & 0xf;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        }
+        else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            }
+            else if self.loop_flag {
+                self.decay = 15;
+            }
+        }
+        else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume { self.volume } else { self.decay }
+    }
+}
+
+struct Pulse {
+    // Pulse 1 negates its sweep with one's complement, pulse 2 with two's
+    ones_complement: bool,
+    enabled: bool,
+
+    duty: u8,
+    duty_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    length_counter: u8,
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Pulse {
+        Pulse {
+            ones_complement: ones_complement,
+            enabled: false,
+            duty: 0,
+            duty_pos: 0,
+            timer_period: 0,
+            timer: 0,
+            length_counter: 0,
+            envelope: Envelope::new(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_reload: false,
+            sweep_divider: 0
+        }
+    }
+
+    fn control_reg_write(&mut self, data: u8) {
+        self.duty = data >> 6;
+        self.envelope.reg_write(data);
+    }
+
+    fn sweep_reg_write(&mut self, data: u8) {
+        self.sweep_enabled = (data & 0x80) == 0x80;
+        self.sweep_period = (data >> 4) & 0x7;
+        self.sweep_negate = (data & 0x8) == 0x8;
+        self.sweep_shift = data & 0x7;
+        self.sweep_reload = true;
+    }
+
+    fn timer_lo_reg_write(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x700) | data as u16;
+    }
+
+    fn timer_hi_reg_write(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff) | (((data & 0x7) as u16) << 8);
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+        self.duty_pos = 0;
+        self.envelope.start = true;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            if self.ones_complement {
+                self.timer_period.saturating_sub(change + 1)
+            }
+            else {
+                self.timer_period.saturating_sub(change)
+            }
+        }
+        else {
+            self.timer_period + change
+        }
+    }
+
+    fn is_sweep_muting(&self) -> bool {
+        (self.timer_period < 8) || (self.sweep_target() > 0x7ff)
+    }
+
+    // Clocked every other CPU tick
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.duty_pos = (self.duty_pos + 1) & 0x7;
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.loop_flag && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if (self.sweep_divider == 0) && self.sweep_enabled &&
+            (self.sweep_shift > 0) && !self.is_sweep_muting() {
+
+            self.timer_period = self.sweep_target();
+        }
+
+        if (self.sweep_divider == 0) || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        }
+        else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if (self.length_counter == 0) || self.is_sweep_muting() ||
+            (DUTY_TABLE[self.duty as usize][self.duty_pos as usize] == 0) {
+            0
+        }
+        else {
+            self.envelope.output()
+        }
+    }
+}
+
+struct Triangle {
+    enabled: bool,
+    control_flag: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    length_counter: u8,
+    sequence_pos: u8
+}
+
+impl Triangle {
+    fn new() -> Triangle {
+        Triangle {
+            enabled: false,
+            control_flag: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            timer_period: 0,
+            timer: 0,
+            length_counter: 0,
+            sequence_pos: 0
+        }
+    }
+
+    fn control_reg_write(&mut self, data: u8) {
+        self.control_flag = (data & 0x80) == 0x80;
+        self.linear_reload_value = data & 0x7f;
+    }
+
+    fn timer_lo_reg_write(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0x700) | data as u16;
+    }
+
+    fn timer_hi_reg_write(&mut self, data: u8) {
+        self.timer_period = (self.timer_period & 0xff) | (((data & 0x7) as u16) << 8);
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+        self.linear_reload = true;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    // Clocked every CPU tick
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if (self.length_counter > 0) && (self.linear_counter > 0) {
+                self.sequence_pos = (self.sequence_pos + 1) & 0x1f;
+            }
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        }
+        else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control_flag {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control_flag && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        // Ultrasonic periods are silenced rather than emulated as a DC offset
+        if self.timer_period < 2 {
+            7
+        }
+        else {
+            TRIANGLE_TABLE[self.sequence_pos as usize]
+        }
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    mode_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    length_counter: u8,
+    envelope: Envelope
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            enabled: false,
+            mode_flag: false,
+            timer_period: NTSC.noise_periods[0],
+            timer: 0,
+            shift_register: 1,
+            length_counter: 0,
+            envelope: Envelope::new()
+        }
+    }
+
+    fn control_reg_write(&mut self, data: u8) {
+        self.envelope.reg_write(data);
+    }
+
+    fn period_reg_write(&mut self, data: u8, periods: &[u16; 16]) {
+        self.mode_flag = (data & 0x80) == 0x80;
+        self.timer_period = periods[(data & 0xf) as usize];
+    }
+
+    fn length_reg_write(&mut self, data: u8) {
+        if self.enabled {
+            self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+        }
+        self.envelope.start = true;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    // Clocked every CPU tick, the period table is already in CPU ticks
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+
+            let other_bit = if self.mode_flag { 6 } else { 1 };
+            let feedback = (self.shift_register & 0x1) ^ ((self.shift_register >> other_bit) & 0x1);
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.loop_flag && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if (self.length_counter == 0) || ((self.shift_register & 0x1) == 0x1) {
+            0
+        }
+        else {
+            self.envelope.output()
+        }
+    }
+}
+
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    loop_flag: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+
+    // Memory reader
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool
+}
+
+impl Dmc {
+    fn new() -> Dmc {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            loop_flag: false,
+            timer_period: NTSC.dmc_rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true
+        }
+    }
+
+    fn control_reg_write(&mut self, data: u8, rates: &[u16; 16]) {
+        self.irq_enabled = (data & 0x80) == 0x80;
+        self.loop_flag = (data & 0x40) == 0x40;
+        self.timer_period = rates[(data & 0xf) as usize];
+        if !self.irq_enabled {
+            self.irq = false;
+        }
+    }
+
+    fn direct_load_reg_write(&mut self, data: u8) {
+        self.output_level = data & 0x7f;
+    }
+
+    fn address_reg_write(&mut self, data: u8) {
+        self.sample_address = 0xc000 + (data as u16) * 64;
+    }
+
+    fn length_reg_write(&mut self, data: u8) {
+        self.sample_length = (data as u16) * 16 + 1;
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        }
+        else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    // The address the memory reader wants to fetch, if it needs a byte
+    fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && (self.bytes_remaining > 0) {
+            Some(self.current_address)
+        }
+        else {
+            None
+        }
+    }
+
+    fn dma_complete(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+
+        self.current_address = if self.current_address == 0xffff { 0x8000 } else { self.current_address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            }
+            else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU tick, the rate table is already in CPU ticks
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.clock_output();
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if (self.shift_register & 0x1) == 0x1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            }
+            else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.shift_register = data;
+                    self.silence = false;
+                },
+                None => self.silence = true
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+// Averages the per-tick mixer output down to the host sample rate.  The
+// ratio can be nudged by the frontend to keep the audio device fed.
+struct Resampler {
+    clock_rate: f64,
+    sample_rate: u32,
+    ticks_per_sample: f64,
+    rate_adjust: f64,
+    position: f64,
+    sum: f32,
+    count: u32,
+
+    // One-pole high-pass to remove the DC offset of the mixer
+    filter_factor: f32,
+    prev_input: f32,
+    prev_output: f32,
+
+    samples: Vec<i16>
+}
+
+impl Resampler {
+    fn new(clock_rate: f64, sample_rate: u32) -> Resampler {
+        let mut resampler = Resampler {
+            clock_rate: clock_rate,
+            sample_rate: sample_rate,
+            ticks_per_sample: 0.0,
+            rate_adjust: 1.0,
+            position: 0.0,
+            sum: 0.0,
+            count: 0,
+            filter_factor: 0.0,
+            prev_input: 0.0,
+            prev_output: 0.0,
+            samples: Vec::new()
+        };
+        resampler.set_sample_rate(sample_rate);
+        resampler
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        use std::f32::consts::PI;
+
+        self.sample_rate = sample_rate;
+        self.ticks_per_sample = self.clock_rate / (sample_rate as f64);
+
+        // ~90Hz cutoff, as on the real console
+        let rc = 1.0 / (2.0 * PI * 90.0);
+        let dt = 1.0 / (sample_rate as f32);
+        self.filter_factor = rc / (rc + dt);
+    }
+
+    fn push(&mut self, input: f32) {
+        self.sum += input;
+        self.count += 1;
+        self.position += 1.0;
+
+        let step = self.ticks_per_sample * self.rate_adjust;
+        if self.position >= step {
+            self.position -= step;
+
+            let average = self.sum / (self.count as f32);
+            self.sum = 0.0;
+            self.count = 0;
+
+            let filtered = self.filter_factor * (self.prev_output + average - self.prev_input);
+            self.prev_input = average;
+            self.prev_output = filtered;
+
+            // Keep no more than a second if nothing is taking them, dropping
+            // the oldest half second at a time
+            let limit = self.sample_rate as usize;
+            if self.samples.len() >= limit {
+                self.samples.drain(..limit / 2);
+            }
+
+            let scaled = (filtered * 32767.0).clamp(-32768.0, 32767.0);
+            self.samples.push(scaled as i16);
+        }
+    }
+}
+
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    frame_tick: u32,
+    frame_5_step_mode: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    frame_reset_delay: u8,
+    odd_tick: bool,
+    timing: &'static Timing,
+
+    pulse_table: Vec<f32>,
+    tnd_table: Vec<f32>,
+
+    resampler: Resampler
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        // Lookup tables for the non-linear mixer
+        let mut pulse_table = vec![0.0; 31];
+        for i in 1..31 {
+            pulse_table[i] = 95.52 / (8128.0 / (i as f32) + 100.0);
+        }
+
+        let mut tnd_table = vec![0.0; 203];
+        for i in 1..203 {
+            tnd_table[i] = 163.67 / (24329.0 / (i as f32) + 100.0);
+        }
+
+        Apu {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_tick: 0,
+            frame_5_step_mode: false,
+            frame_irq_inhibit: false,
+            frame_irq: false,
+            frame_reset_delay: 0,
+            odd_tick: false,
+            timing: &NTSC,
+            pulse_table: pulse_table,
+            tnd_table: tnd_table,
+            resampler: Resampler::new(NTSC.cpu_clock_rate, DEFAULT_SAMPLE_RATE)
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_sample_rate(sample_rate);
+    }
+
+    // Takes a cart::region value.  The clock rate changes with it, so a
+    // second of emulated time still makes a second of samples.
+    pub fn set_region(&mut self, region: u8) {
+        self.timing = match region {
+            region::PAL => &PAL,
+            region::DENDY => &DENDY,
+            _ => &NTSC
+        };
+
+        let sample_rate = self.resampler.sample_rate;
+        self.resampler.clock_rate = self.timing.cpu_clock_rate;
+        self.resampler.set_sample_rate(sample_rate);
+    }
+
+    // Scales the number of CPU ticks per output sample.  Values above 1.0
+    // produce fewer samples, values below 1.0 produce more.
+    pub fn set_rate_adjust(&mut self, rate_adjust: f64) {
+        self.resampler.rate_adjust = rate_adjust;
+    }
+
+    // Hands over the samples produced since the last call, up to a second's
+    // worth
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        use std::mem;
+
+        mem::take(&mut self.resampler.samples)
+    }
+
+    pub fn channel_reg_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x4000 => self.pulse_1.control_reg_write(data),
+            0x4001 => self.pulse_1.sweep_reg_write(data),
+            0x4002 => self.pulse_1.timer_lo_reg_write(data),
+            0x4003 => self.pulse_1.timer_hi_reg_write(data),
+            0x4004 => self.pulse_2.control_reg_write(data),
+            0x4005 => self.pulse_2.sweep_reg_write(data),
+            0x4006 => self.pulse_2.timer_lo_reg_write(data),
+            0x4007 => self.pulse_2.timer_hi_reg_write(data),
+            0x4008 => self.triangle.control_reg_write(data),
+            0x400A => self.triangle.timer_lo_reg_write(data),
+            0x400B => self.triangle.timer_hi_reg_write(data),
+            0x400C => self.noise.control_reg_write(data),
+            0x400E => self.noise.period_reg_write(data, &self.timing.noise_periods),
+            0x400F => self.noise.length_reg_write(data),
+            0x4010 => self.dmc.control_reg_write(data, &self.timing.dmc_rates),
+            0x4011 => self.dmc.direct_load_reg_write(data),
+            0x4012 => self.dmc.address_reg_write(data),
+            0x4013 => self.dmc.length_reg_write(data),
+            _ => {} // 0x4009 and 0x400D are unused
+        }
+    }
+
+    pub fn status_reg_write(&mut self, data: u8) {
+        self.pulse_1.set_enabled((data & 0x1) == 0x1);
+        self.pulse_2.set_enabled((data & 0x2) == 0x2);
+        self.triangle.set_enabled((data & 0x4) == 0x4);
+        self.noise.set_enabled((data & 0x8) == 0x8);
+        self.dmc.set_enabled((data & 0x10) == 0x10);
+    }
+
+    pub fn status_reg_read(&mut self) -> u8 {
+        let mut result: u8 = 0;
+
+        if self.pulse_1.length_counter > 0 {
+            result += 0x1;
+        }
+        if self.pulse_2.length_counter > 0 {
+            result += 0x2;
+        }
+        if self.triangle.length_counter > 0 {
+            result += 0x4;
+        }
+        if self.noise.length_counter > 0 {
+            result += 0x8;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            result += 0x10;
+        }
+        if self.frame_irq {
+            result += 0x40;
+        }
+        if self.dmc.irq {
+            result += 0x80;
+        }
+
+        // Reading the status acknowledges the frame interrupt
+        self.frame_irq = false;
+
+        result
+    }
+
+    pub fn frame_counter_reg_write(&mut self, data: u8) {
+        self.frame_5_step_mode = (data & 0x80) == 0x80;
+        self.frame_irq_inhibit = (data & 0x40) == 0x40;
+        if self.frame_irq_inhibit {
+            self.frame_irq = false;
+        }
+
+        // The sequencer restarts 3 or 4 ticks later, depending on whether
+        // the write landed on an APU cycle
+        self.frame_reset_delay = if self.odd_tick { 4 } else { 3 };
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Whether the tick about to be run is an odd one
+    pub fn odd_tick(&self) -> bool {
+        self.odd_tick
+    }
+
+    // The DMC memory reader can't see the bus itself, so the MMU polls it
+    // and hands back the byte it asked for
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    pub fn dmc_dma_complete(&mut self, data: u8) {
+        self.dmc.dma_complete(data);
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_length();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_length();
+        self.pulse_2.clock_sweep();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    fn set_frame_irq(&mut self) {
+        if !self.frame_irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+            if self.frame_reset_delay == 0 {
+                self.frame_tick = 0;
+                if self.frame_5_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_tick += 1;
+
+        let tick = self.frame_tick;
+        let timing = self.timing;
+        if tick == timing.frame_step_1 || tick == timing.frame_step_3 {
+            self.clock_quarter_frame();
+        }
+        else if tick == timing.frame_step_2 {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+        else if self.frame_5_step_mode {
+            if tick == timing.frame_step_5 {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            else if tick == timing.frame_length_5_step {
+                self.frame_tick = 0;
+            }
+        }
+        // The interrupt flag is raised on the last three ticks of the sequence
+        else if tick == timing.frame_irq_start {
+            self.set_frame_irq();
+        }
+        else if tick == timing.frame_step_4 {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+            self.set_frame_irq();
+        }
+        else if tick == timing.frame_length_4_step {
+            self.set_frame_irq();
+            self.frame_tick = 0;
+        }
+    }
+
+    // Advances the APU by one CPU tick
+    pub fn tick(&mut self) {
+        self.clock_frame_sequencer();
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.odd_tick {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_tick = !self.odd_tick;
+
+        let output = self.output();
+        self.resampler.push(output);
+    }
+
+    // Mixed output of all channels, in the range 0.0 - 1.0
+    fn output(&self) -> f32 {
+        let pulse_out = self.pulse_table[(self.pulse_1.output() + self.pulse_2.output()) as usize];
+        let tnd_out = self.tnd_table[(3 * self.triangle.output() as usize) +
+            (2 * self.noise.output() as usize) + self.dmc.output() as usize];
+
+        pulse_out + tnd_out
+    }
+}
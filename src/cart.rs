@@ -1,174 +1,644 @@
 use std::io;
+use std::cmp;
 use std::io::prelude::*;
 use std::fs::File;
+use std::fmt;
+use std::error;
+use std::path::Path;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
 use util::{BitReader, Joiner};
 use mmu::Mmu;
 use ppu::mirroring;
+use mapper;
+use mapper::Cart;
+use gamedb;
+use gamedb::Game;
+use patch;
 
-fn configure_ppu_for_cart(mmu: &mut Mmu) {
-    //Check for workarounds
-    mmu.ppu.fix_bg_change =
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfeb] == b'Z') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfec] == b'E') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfed] == b'L') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfee] == b'D') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfef] == b'A');
-    
-    mmu.ppu.fix_scroll_offset_1 =
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe0] == b'B') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe1] == b'B') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe2] == b'4') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe3] == b'7') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe4] == b'9') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe5] == b'5') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe6] == b'6') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe7] == b'-') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe8] == b'1') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfe9] == b'5') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfea] == b'4') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfeb] == b'4') &&
-        (mmu.prg_rom[mmu.num_prg_pages - 1][0xfec] == b'0');
-        
-    mmu.ppu.fix_scroll_offset_2 = 
-        (mmu.prg_rom[0][0x9] == 0xfc) &&
-        (mmu.prg_rom[0][0xa] == 0xfc) &&
-        (mmu.prg_rom[0][0xb] == 0xfc) &&
-        (mmu.prg_rom[0][0xc] == 0x40) &&
-        (mmu.prg_rom[0][0xd] == 0x40) &&
-        (mmu.prg_rom[0][0xe] == 0x40) &&
-        (mmu.prg_rom[0][0xf] == 0x40);
-        
-    mmu.ppu.fix_scroll_offset_3 = 
-        (mmu.prg_rom[0][0x75] == 0x11) &&
-        (mmu.prg_rom[0][0x76] == 0x12) &&
-        (mmu.prg_rom[0][0x77] == 0x13) &&
-        (mmu.prg_rom[0][0x78] == 0x14) &&
-        (mmu.prg_rom[0][0x79] == 0x07) && 
-        (mmu.prg_rom[0][0x7a] == 0x03) && 
-        (mmu.prg_rom[0][0x7b] == 0x03) && 
-        (mmu.prg_rom[0][0x7c] == 0x03) && 
-        (mmu.prg_rom[0][0x7d] == 0x03);  
-    
-    mmu.ppu.fix_scroll_reset = 
-        (mmu.prg_rom[0][0xfeb - 0x10] == 0xFA) &&
-        (mmu.prg_rom[0][0xfec - 0x10] == 0xA9) &&
-        (mmu.prg_rom[0][0xfed - 0x10] == 0x18);
-        
+// CPU/PPU timing the cart was made for
+pub mod region {
+    pub const NTSC     : u8 = 0;
+    pub const PAL      : u8 = 1;
+    pub const MULTIPLE : u8 = 2;
+    pub const DENDY    : u8 = 3;
 }
 
+pub mod console {
+    pub const NES        : u8 = 0;
+    pub const VS_SYSTEM  : u8 = 1;
+    pub const PLAYCHOICE : u8 = 2;
+    pub const EXTENDED   : u8 = 3;
+}
 
-pub fn load_cart(fname: &String, mmu: &mut Mmu) -> Result<(), io::Error> {
-    use std::io::{Error, ErrorKind};
-    let mut f = try!(File::open(fname));
-    
-    let nes_header_id = try!(f.read_u32_be());
-    
-    //Check to see if the 'NES ' is there
-    if nes_header_id != 0x4e45531A {
-        return Err(Error::new(ErrorKind::InvalidInput, "File is not a compatible .nes file"));
+// Why a file couldn't be loaded as a cart
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // Not an iNES, NES 2.0 or UNIF image
+    BadMagic,
+    // The header describes something no file could hold
+    BadHeader(String),
+    // The file ends before the sizes in its header say it should
+    Truncated { expected: usize, got: usize },
+    UnsupportedMapper(u16),
+    UnsupportedBoard(String),
+    BadUnif(String),
+    BadArchive(String),
+    BadPatch(String)
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "{}", e),
+            LoadError::BadMagic => write!(f, "File is not a compatible .nes or .unf file"),
+            LoadError::BadHeader(ref reason) => write!(f, "Bad header: {}", reason),
+            LoadError::Truncated { expected, got } =>
+                write!(f, "File is cut short: expected {} bytes, got {}", expected, got),
+            LoadError::UnsupportedMapper(number) => write!(f, "Unsupported mapper: {}", number),
+            LoadError::UnsupportedBoard(ref board) => write!(f, "Unsupported board: {}", board),
+            LoadError::BadUnif(ref reason) => write!(f, "Bad UNIF image: {}", reason),
+            LoadError::BadArchive(ref reason) => write!(f, "Bad archive: {}", reason),
+            LoadError::BadPatch(ref reason) => write!(f, "Bad patch: {}", reason)
+        }
     }
-    
-    let num_prg_pages = try!(f.read_u8());
-    let num_chr_pages = try!(f.read_u8());
-    let is_vram = num_chr_pages == 0;
-    let cart_info = try!(f.read_u8());
-    let mirroring = 
-        if (cart_info & 0x8) == 0x8 {
-            mirroring::FOUR_SCREEN
-        } 
-        else if (cart_info & 0x1) == 0x1 { 
-            mirroring::VERTICAL 
-        } 
-        else { 
-            mirroring::HORIZONTAL 
-        };
-    let save_ram_present = (cart_info & 0x2) == 0x2;
-    let _ = (cart_info & 0x4) == 0x4; //trainer_present
-    let mapper_part = try!(f.read_u8());
-    let mapper = 
-        if mapper_part == 0x44 {
-            // Disk dude garbage
-            cart_info >> 4
+}
+
+impl error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+// For callers that only deal in io::Error, eg: the frontend
+impl From<LoadError> for io::Error {
+    fn from(e: LoadError) -> io::Error {
+        match e {
+            LoadError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e)
+        }
+    }
+}
+
+// Reads up to count bytes, which is only less at the end of the file
+fn read_up_to<R: Read>(f: &mut R, count: usize) -> Result<Vec<u8>, LoadError> {
+    let mut bytes = Vec::new();
+    try!(f.by_ref().take(count as u64).read_to_end(&mut bytes));
+    Ok(bytes)
+}
+
+// Everything the 16 byte iNES or NES 2.0 header, or a UNIF image's
+// chunks, say about the cart.  Sizes are in bytes.
+#[derive(Clone, Debug)]
+pub struct CartHeader {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub mirroring: u8,
+    pub battery: bool,
+    pub trainer: bool,
+
+    pub region: u8,
+    pub console: u8,
+    // Only meaningful for Vs. System carts
+    pub vs_ppu_type: u8,
+    pub vs_hardware_type: u8,
+    pub extended_console: u8,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+
+    // gamedb::quirk flags from the game's database entry
+    pub quirks: u8,
+
+    // Only UNIF images have these
+    pub board: Option<String>,
+    pub name: Option<String>
+}
+
+// NES 2.0 RAM sizes are a shift count: 64 << n bytes, or none for 0
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+// NES 2.0 ROM sizes are in units, unless the MSB nibble is $F, in which
+// case the LSB holds an exponent and multiplier: 2^E * (MM * 2 + 1).  None
+// if that doesn't fit in a usize.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0xf {
+        1usize.checked_shl((lsb >> 2) as u32)
+            .and_then(|size| size.checked_mul(((lsb & 0x3) as usize) * 2 + 1))
+    }
+    else {
+        Some((((msb as usize) << 8) + lsb as usize) * unit)
+    }
+}
+
+fn size_text(size: usize) -> String {
+    if size % 1024 == 0 {
+        format!("{}k", size / 1024)
+    }
+    else {
+        format!("{} bytes", size)
+    }
+}
+
+impl fmt::Display for CartHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.board {
+            Some(ref board) => writeln!(f, "Format: UNIF (board {})", board),
+            None => writeln!(f, "Format: {}", self.format_name())
+        });
+        if let Some(ref name) = self.name {
+            try!(writeln!(f, "Name: {}", name));
+        }
+        try!(writeln!(f, "Mapper: {} ({}), submapper {}",
+            self.mapper, mapper::name(self.mapper).unwrap_or("unsupported"), self.submapper));
+        try!(writeln!(f, "PRG ROM: {}, CHR ROM: {}", size_text(self.prg_rom_size), size_text(self.chr_rom_size)));
+        try!(writeln!(f, "PRG RAM: {}, PRG NVRAM: {}{}", size_text(self.prg_ram_size), size_text(self.prg_nvram_size),
+            if self.battery { " (battery)" } else { "" }));
+        try!(writeln!(f, "CHR RAM: {}, CHR NVRAM: {}", size_text(self.chr_ram_size), size_text(self.chr_nvram_size)));
+        try!(writeln!(f, "Mirroring: {}", self.mirroring_name()));
+        try!(writeln!(f, "Region: {}", self.region_name()));
+        try!(writeln!(f, "Console: {}", match self.console {
+            console::VS_SYSTEM => format!("Vs. System (PPU {}, hardware {})", self.vs_ppu_type, self.vs_hardware_type),
+            console::PLAYCHOICE => String::from("PlayChoice-10"),
+            console::EXTENDED => format!("extended type {}", self.extended_console),
+            _ => String::from("NES")
+        }));
+        try!(writeln!(f, "Expansion device: {}, misc ROMs: {}", self.expansion_device, self.misc_roms));
+        write!(f, "Trainer: {}", if self.trainer { "512 bytes at $7000-$71FF" } else { "none" })
+    }
+}
+
+impl CartHeader {
+    // A blank NROM cart
+    pub fn new() -> CartHeader {
+        CartHeader {
+            nes2: false,
+            mapper: 0,
+            submapper: 0,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            prg_ram_size: 0x2000,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring: mirroring::HORIZONTAL,
+            battery: false,
+            trainer: false,
+            region: region::NTSC,
+            console: console::NES,
+            vs_ppu_type: 0,
+            vs_hardware_type: 0,
+            extended_console: 0,
+            misc_roms: 0,
+            expansion_device: 0,
+            quirks: 0,
+            board: None,
+            name: None
+        }
+    }
+
+    pub fn format_name(&self) -> &'static str {
+        match self.board {
+            Some(_) => "UNIF",
+            None => if self.nes2 { "NES 2.0" } else { "iNES" }
+        }
+    }
+
+    pub fn mirroring_name(&self) -> &'static str {
+        match self.mirroring {
+            mirroring::FOUR_SCREEN => "four screen",
+            mirroring::VERTICAL => "vertical",
+            mirroring::ONE_SCREEN_LOW | mirroring::ONE_SCREEN_HIGH => "one screen",
+            _ => "horizontal"
+        }
+    }
+
+    pub fn region_name(&self) -> &'static str {
+        match self.region {
+            region::PAL => "PAL",
+            region::MULTIPLE => "multiple",
+            region::DENDY => "Dendy",
+            _ => "NTSC"
+        }
+    }
+
+    pub fn parse(bytes: &[u8; 16]) -> Result<CartHeader, LoadError> {
+        //Check to see if the 'NES ' is there
+        if bytes[0..4] != [0x4e, 0x45, 0x53, 0x1a] {
+            return Err(LoadError::BadMagic);
         }
-        else if (cart_info == 0x23) && (mapper_part == 0x64) {
-            2
+
+        let mut header = CartHeader::new();
+        let flags_6 = bytes[6];
+        let flags_7 = bytes[7];
+
+        header.mirroring =
+            if (flags_6 & 0x8) == 0x8 {
+                mirroring::FOUR_SCREEN
+            }
+            else if (flags_6 & 0x1) == 0x1 {
+                mirroring::VERTICAL
+            }
+            else {
+                mirroring::HORIZONTAL
+            };
+        header.battery = (flags_6 & 0x2) == 0x2;
+        header.trainer = (flags_6 & 0x4) == 0x4;
+        header.console = flags_7 & 0x3;
+        header.nes2 = (flags_7 & 0xc) == 0x8;
+
+        if header.nes2 {
+            header.mapper = ((flags_6 >> 4) as u16) | ((flags_7 & 0xf0) as u16) | (((bytes[8] & 0xf) as u16) << 8);
+            header.submapper = bytes[8] >> 4;
+            header.prg_rom_size = try!(rom_size(bytes[4], bytes[9] & 0xf, 0x4000)
+                .ok_or_else(|| LoadError::BadHeader(String::from("PRG ROM size is too large"))));
+            header.chr_rom_size = try!(rom_size(bytes[5], bytes[9] >> 4, 0x2000)
+                .ok_or_else(|| LoadError::BadHeader(String::from("CHR ROM size is too large"))));
+            header.prg_ram_size = ram_size(bytes[10] & 0xf);
+            header.prg_nvram_size = ram_size(bytes[10] >> 4);
+            header.chr_ram_size = ram_size(bytes[11] & 0xf);
+            header.chr_nvram_size = ram_size(bytes[11] >> 4);
+            header.region = bytes[12] & 0x3;
+
+            match header.console {
+                console::VS_SYSTEM => {
+                    header.vs_ppu_type = bytes[13] & 0xf;
+                    header.vs_hardware_type = bytes[13] >> 4;
+                },
+                console::EXTENDED => header.extended_console = bytes[13] & 0xf,
+                _ => {}
+            }
+
+            header.misc_roms = bytes[14] & 0x3;
+            header.expansion_device = bytes[15] & 0x3f;
         }
         else {
-            (cart_info >> 4) + (mapper_part & 0xf0)
+            // Old dumping tools left their names in bytes 7-15, eg:
+            // "DiskDude!".  The top of the mapper number can't be trusted
+            // if the end of the header isn't blank, or byte 7 has bits
+            // set that neither iNES nor NES 2.0 use there.
+            let dirty = bytes[12..16].iter().any(|&b| b != 0) || (flags_7 & 0xc) == 0x4;
+
+            header.mapper =
+                if dirty {
+                    (flags_6 >> 4) as u16
+                }
+                else {
+                    ((flags_6 >> 4) | (flags_7 & 0xf0)) as u16
+                };
+            if dirty {
+                header.console = console::NES;
+            }
+
+            header.prg_rom_size = (bytes[4] as usize) * 0x4000;
+            header.chr_rom_size = (bytes[5] as usize) * 0x2000;
+
+            // The RAM sizes weren't recorded, so assume the usual 8k
+            if header.battery {
+                header.prg_ram_size = 0;
+                header.prg_nvram_size = 0x2000;
+            }
+            if header.chr_rom_size == 0 {
+                header.chr_ram_size = 0x2000;
+            }
+            if !dirty && (bytes[9] & 0x1) == 0x1 {
+                header.region = region::PAL;
+            }
+        }
+
+        Ok(header)
+    }
+}
+
+// UNIF images are a 32 byte header, then chunks of a four character ID, a
+// little endian length and data.  Returns the ROM from PRG0-PRGF and
+// CHR0-CHRF, concatenated in order.
+fn parse_unif(image: &[u8]) -> Result<(CartHeader, Vec<u8>, Vec<u8>), LoadError> {
+    if image.len() < 32 {
+        return Err(LoadError::Truncated { expected: 32, got: image.len() });
+    }
+
+    let mut chunks = &image[32..];
+    let mut header = CartHeader::new();
+    let mut prg_chunks : Vec<Option<&[u8]>> = vec![None; 16];
+    let mut chr_chunks : Vec<Option<&[u8]>> = vec![None; 16];
+    let mut mirr = None;
+
+    while !chunks.is_empty() {
+        let position = image.len() - chunks.len();
+        if chunks.len() < 8 {
+            return Err(LoadError::Truncated { expected: position + 8, got: image.len() });
+        }
+
+        let id = &chunks[..4];
+        chunks = &chunks[4..];
+        let length = try!(chunks.read_u32_le()) as usize;
+        if length > chunks.len() {
+            return Err(LoadError::Truncated { expected: position + 8 + length, got: image.len() });
+        }
+        let (data, rest) = chunks.split_at(length);
+        chunks = rest;
+
+        // Strings are zero terminated
+        let text = || String::from_utf8_lossy(data.split(|&b| b == 0).next().unwrap_or(data)).into_owned();
+
+        match id {
+            b"MAPR" => header.board = Some(text()),
+            b"NAME" => header.name = Some(text()),
+            b"BATR" => header.battery = true,
+            b"MIRR" if !data.is_empty() => mirr = Some(data[0]),
+            _ if &id[..3] == b"PRG" || &id[..3] == b"CHR" => {
+                let index = match (id[3] as char).to_digit(16) {
+                    Some(index) => index as usize,
+                    None => continue
+                };
+                if &id[..3] == b"PRG" { prg_chunks[index] = Some(data); } else { chr_chunks[index] = Some(data); }
+            },
+            _ => {}
+        }
+    }
+
+    let board = match header.board {
+        Some(ref board) => board.clone(),
+        None => return Err(LoadError::BadUnif(String::from("no MAPR chunk")))
+    };
+    header.mapper = match mapper::unif_board_number(&board) {
+        Some(number) => number,
+        None => return Err(LoadError::UnsupportedBoard(board))
+    };
+
+    // 5 leaves mirroring to the mapper
+    header.mirroring = match mirr {
+        Some(1) => mirroring::VERTICAL,
+        Some(2) => mirroring::ONE_SCREEN_LOW,
+        Some(3) => mirroring::ONE_SCREEN_HIGH,
+        Some(4) => mirroring::FOUR_SCREEN,
+        _ => mirroring::HORIZONTAL
+    };
+
+    let prg_rom : Vec<u8> = prg_chunks.iter().filter_map(|&c| c).flat_map(|c| c.iter().cloned()).collect();
+    let chr_rom : Vec<u8> = chr_chunks.iter().filter_map(|&c| c).flat_map(|c| c.iter().cloned()).collect();
+    if prg_rom.is_empty() {
+        return Err(LoadError::BadUnif(String::from("no PRG chunks")));
+    }
+
+    header.prg_rom_size = prg_rom.len();
+    header.chr_rom_size = chr_rom.len();
+    if header.battery {
+        header.prg_ram_size = 0;
+        header.prg_nvram_size = 0x2000;
+    }
+    if chr_rom.is_empty() {
+        header.chr_ram_size = 0x2000;
+    }
+
+    Ok((header, prg_rom, chr_rom))
+}
+
+
+// A ROM image as its header describes it, before any database fixes
+pub struct RomImage {
+    pub header: CartHeader,
+    // Trainers sit between the header and PRG, and get copied to $7000
+    pub trainer: [u8; 512],
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>
+}
+
+// Reads an iNES or UNIF image from any reader
+pub fn read_image<R: Read>(f: &mut R) -> Result<RomImage, LoadError> {
+    let start = try!(read_up_to(f, 16));
+    if !start.starts_with(b"NES\x1a") && !start.starts_with(b"UNIF") {
+        return Err(LoadError::BadMagic);
+    }
+    if start.len() < 16 {
+        return Err(LoadError::Truncated { expected: 16, got: start.len() });
+    }
+
+    let mut trainer = [0; 512];
+
+    let (header, prg_rom, chr_rom) =
+        if start.starts_with(b"UNIF") {
+            let mut image = start;
+            try!(f.read_to_end(&mut image));
+            try!(parse_unif(&image))
+        }
+        else {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(&start);
+            let header = try!(CartHeader::parse(&bytes));
+
+            let trainer_size = if header.trainer { trainer.len() } else { 0 };
+            let expected = try!(header.prg_rom_size.checked_add(header.chr_rom_size)
+                .and_then(|size| size.checked_add(trainer_size))
+                .ok_or_else(|| LoadError::BadHeader(String::from("ROM sizes are too large"))));
+            let body = try!(read_up_to(f, expected));
+            if body.len() < expected {
+                return Err(LoadError::Truncated { expected: 16 + expected, got: 16 + body.len() });
+            }
+
+            let (trainer_data, rom) = body.split_at(trainer_size);
+            trainer[..trainer_size].copy_from_slice(trainer_data);
+            let (prg_rom, chr_rom) = rom.split_at(header.prg_rom_size);
+
+            (header, prg_rom.to_vec(), chr_rom.to_vec())
         };
 
-    if !(vec![0, 1, 2, 3, 4].contains(&mapper)) {
-        return Err(Error::new(ErrorKind::InvalidInput, format!("Unsupport mapper: {}", mapper)));
+    Ok(RomImage { header: header, trainer: trainer, prg_rom: prg_rom, chr_rom: chr_rom })
+}
+
+// Loads an iNES or UNIF image from any reader into the MMU and PPU
+pub fn load_rom<R: Read>(f: &mut R, mmu: &mut Mmu) -> Result<(), LoadError> {
+    load_rom_with_games(f, mmu, gamedb::GAMES)
+}
+
+// Loads an image, correcting its header from the given game database
+pub fn load_rom_with_games<R: Read>(f: &mut R, mmu: &mut Mmu, games: &[Game]) -> Result<(), LoadError> {
+    let RomImage { mut header, trainer, prg_rom, chr_rom } = try!(read_image(f));
+
+    // Known dumps with bad headers get corrected here
+    if let Some(game) = gamedb::find_in(games, &prg_rom, &chr_rom) {
+        game.apply(&mut header);
     }
 
-    let mut _unused_buffer = [0; 8];
-    try!(f.read(&mut _unused_buffer));
-    
-    let mut prg_rom : Vec<Vec<u8>> = Vec::new();
-    for _ in 0..(num_prg_pages*4) {
-        let mut buffer = [0; 4096];
-        try!(f.read(&mut buffer));
-        prg_rom.push(buffer.iter().cloned().collect());
-    }
-    let mut chr_rom : Vec<Vec<u8>> = Vec::new();
-    if num_chr_pages > 0 {            
-        for _ in 0..(num_chr_pages*8) {
-            let mut buffer = [0; 1024];
-            try!(f.read(&mut buffer));
-            chr_rom.push(buffer.iter().cloned().collect());
-        }    
+    let number = header.mapper;
+    let mut cart = Cart::new(header, &prg_rom, &chr_rom);
+    if cart.header.trainer {
+        cart.prg_ram[0x1000..0x1200].copy_from_slice(&trainer);
+    }
+
+    match mapper::create(number, cart) {
+        Some(board) => mmu.mapper = board,
+        None => return Err(LoadError::UnsupportedMapper(number))
+    }
+
+    Ok(())
+}
+
+// Extensions of the files an archive is searched for
+const ROM_EXTENSIONS : [&str; 4] = ["nes", "unf", "unif", "fds"];
+
+fn is_rom_name(name: &str) -> bool {
+    match name.rsplit('.').next() {
+        Some(ext) => ROM_EXTENSIONS.contains(&&*ext.to_lowercase()),
+        None => false
+    }
+}
+
+fn read_zip_entry(data: Vec<u8>, inner: Option<&str>) -> Result<(Vec<u8>, String), LoadError> {
+    use std::io::Cursor;
+
+    let mut archive = try!(ZipArchive::new(Cursor::new(data)).map_err(|e| LoadError::BadArchive(e.to_string())));
+
+    let index = match inner {
+        Some(name) => (0..archive.len()).find(|&i| archive.by_index(i).map(|f| f.name() == name).unwrap_or(false)),
+        None => (0..archive.len()).find(|&i| archive.by_index(i).map(|f| is_rom_name(f.name())).unwrap_or(false))
+    };
+    let index = match index {
+        Some(index) => index,
+        None => return Err(LoadError::BadArchive(match inner {
+            Some(name) => format!("no {} inside", name),
+            None => String::from("no .nes, .unf or .fds file inside")
+        }))
+    };
+
+    let mut entry = try!(archive.by_index(index).map_err(|e| LoadError::BadArchive(e.to_string())));
+    let mut rom = Vec::new();
+    try!(entry.read_to_end(&mut rom).map_err(|e| LoadError::BadArchive(e.to_string())));
+
+    Ok((rom, String::from(entry.name())))
+}
+
+// Reads a ROM, unpacking it if it's zipped or gzipped.  A zip's first ROM
+// is used unless one is named after a colon, eg: "roms.zip:smb.nes".
+// Also returns the ROM's name, as a path next to any archive it came from.
+pub fn read_rom_file(fname: &str) -> Result<(Vec<u8>, String), LoadError> {
+    // Only ASCII is lowercased, so the offsets found line up with fname
+    let (path, inner) =
+        match fname.to_ascii_lowercase().find(".zip:") {
+            Some(i) if !Path::new(fname).exists() => (&fname[..i + 4], Some(&fname[i + 5..])),
+            _ => (fname, None)
+        };
+
+    let mut data = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut data));
+
+    if data.starts_with(b"PK\x03\x04") {
+        let (rom, name) = try!(read_zip_entry(data, inner));
+
+        // Entries can be in folders inside the archive, which the save
+        // file shouldn't be
+        let file_name = Path::new(&name).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(name);
+        let rom_name = Path::new(path).with_file_name(file_name).to_string_lossy().into_owned();
+
+        Ok((rom, rom_name))
+    }
+    else if data.starts_with(&[0x1f, 0x8b]) {
+        let mut rom = Vec::new();
+        try!(GzDecoder::new(&data[..]).read_to_end(&mut rom).map_err(|e| LoadError::BadArchive(e.to_string())));
+
+        let rom_name = if path.to_ascii_lowercase().ends_with(".gz") { &path[..path.len() - 3] } else { path };
+        Ok((rom, String::from(rom_name)))
     }
     else {
-        for _ in 0..8 {
-            chr_rom.push(vec![0; 1024]);
-        }
-    }
-
-    mmu.prg_rom = prg_rom;
-    mmu.ppu.chr_rom = chr_rom;
-    mmu.ppu.mirroring = mirroring;
-    mmu.ppu.mirroring_base = 0;
-    mmu.save_ram_present = save_ram_present;
-    mmu.ppu.is_vram = is_vram;
-    mmu.ppu.mapper = mapper;
-    mmu.num_prg_pages = num_prg_pages as usize;
-    mmu.ppu.num_chr_pages = num_chr_pages as usize;
-
-    mmu.setup_defaults();
-    configure_ppu_for_cart(mmu);
-
-    if save_ram_present {
-        let mut fname_split: Vec<&str> = fname.split('.').collect();
-        let save_file_name = 
-            match fname_split.last() {
-                Some(&"nes") => {fname_split.pop(); fname_split.push("sav"); fname_split.join('.')},
-                _ => {fname_split.push("sav"); fname_split.join('.')}
-            };
+        Ok((data, String::from(path)))
+    }
+}
+
+// Names a file that goes with the ROM, eg: "smb.nes" has "smb.sav"
+fn sibling_file_name(rom_name: &str, ext: &str) -> String {
+    let mut fname_split: Vec<&str> = rom_name.split('.').collect();
+    let is_rom_ext = fname_split.len() > 1 && is_rom_name(rom_name);
 
-        let mut save_file_open = File::open(save_file_name.clone());
-        match save_file_open {
-            Ok(ref mut save_file) => {
-                let mut buff = [0; 0x2000];
-                let result = save_file.read(&mut buff);
-                match result {
-                    Ok(_) => mmu.save_ram = buff.iter().cloned().collect(),
-                    _ => {}
+    if is_rom_ext {
+        fname_split.pop();
+    }
+    fname_split.push(ext);
+    fname_split.join('.')
+}
+
+pub fn save_file_name(rom_name: &str) -> String {
+    sibling_file_name(rom_name, "sav")
+}
+
+// Applies the patch given, or else any .ips, .ups or .bps named after the ROM
+fn patch_rom(rom: Vec<u8>, rom_name: &str, patch_file: Option<&String>) -> Result<Vec<u8>, LoadError> {
+    let patch_name = match patch_file {
+        Some(name) => name.clone(),
+        None => match ["ips", "ups", "bps"].iter()
+                .map(|ext| sibling_file_name(rom_name, ext))
+                .find(|name| Path::new(name).exists()) {
+            Some(name) => name,
+            None => return Ok(rom)
+        }
+    };
+
+    let mut patch = Vec::new();
+    try!(try!(File::open(&patch_name)).read_to_end(&mut patch));
+
+    patch::apply(&patch, &rom).map_err(|e| match e {
+        LoadError::BadPatch(reason) => LoadError::BadPatch(format!("{}: {}", patch_name, reason)),
+        e => e
+    })
+}
+
+// Reads a ROM file the way load_cart does, unpacked and patched.  Also
+// returns the ROM's name, see read_rom_file.
+pub fn read_patched_rom_file(fname: &str, patch_file: Option<&String>) -> Result<(Vec<u8>, String), LoadError> {
+    let (rom, rom_name) = try!(read_rom_file(fname));
+    let rom = try!(patch_rom(rom, &rom_name, patch_file));
+
+    Ok((rom, rom_name))
+}
+
+// Loads a ROM file into the MMU and PPU, along with its save RAM.  Saves
+// are kept next to the ROM unless a save directory is given.
+pub fn load_cart(fname: &String, patch_file: Option<&String>, save_dir: Option<&String>, mmu: &mut Mmu) -> Result<(), LoadError> {
+    let (rom, rom_name) = try!(read_patched_rom_file(fname, patch_file));
+    
+    try!(load_rom(&mut &rom[..], mmu));
+
+    if mmu.mapper.cart().header.battery {
+        let save_file_name = match save_dir {
+            Some(dir) => {
+                let save_name = save_file_name(&rom_name);
+                let file_name = Path::new(&save_name).file_name().map(|n| n.to_os_string()).unwrap_or_default();
+                Path::new(dir).join(file_name).to_string_lossy().into_owned()
+            },
+            None => save_file_name(&rom_name)
+        };
+        // The save is all of PRG RAM, as the frontend writes it out.  One
+        // from before the RAM grew, or that can't be read, still leaves the
+        // game playable, so it's only warned about.
+        let mut save = Vec::new();
+        match File::open(&save_file_name).and_then(|mut f| f.read_to_end(&mut save)) {
+            Ok(_) => {
+                let prg_ram = &mut mmu.mapper.cart_mut().prg_ram;
+                if save.len() < prg_ram.len() {
+                    println!("Warning: {} is {} bytes, short of {}.  The rest of PRG RAM is left clear.",
+                        save_file_name, save.len(), prg_ram.len());
                 }
+                let size = cmp::min(save.len(), prg_ram.len());
+                prg_ram[..size].copy_from_slice(&save[..size]);
             },
-            _ => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => println!("Warning: could not read {}, starting without it.  {}", save_file_name, e)
         }
         mmu.save_ram_file_name = save_file_name;
     }
 
-    /*
-    println!("Prg roms: {}", num_prg_pages * 4);
-    println!("Chr roms: {}", num_chr_pages * 8);
-    println!("Is_vram: {}", is_vram);
-    println!("Mirroring: {:?}", mirroring);
-    println!("Save ram present: {}", save_ram_present);
-    println!("Trainer present: {}", trainer_present);
-    println!("Mapper: {}", mapper);
-    */
-
     Ok(())
 }
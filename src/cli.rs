@@ -0,0 +1,203 @@
+// Command line parsing.  Commands and options are described once, in the
+// tables below, and both the parser and the usage text are built from them.
+
+use rustynes::cart::region;
+
+struct CommandDef {
+    name: &'static str,
+    args: &'static str,
+    help: &'static str
+}
+
+static COMMANDS : &[CommandDef] = &[
+    CommandDef { name: "run", args: "<rom>", help: "Play a ROM in a window.  The command used when none is given" },
+    CommandDef { name: "info", args: "<rom>...", help: "Print what the loader makes of ROMs" },
+    CommandDef { name: "headless", args: "<rom>", help: "Run a ROM without a window, until --frames or the movie runs out" },
+    CommandDef { name: "test", args: "<rom>...", help: "Run test ROMs that report through $6000 and print their results" },
+];
+
+struct OptionDef {
+    name: &'static str,
+    value: Option<&'static str>,
+    help: &'static str,
+    // The commands that take it
+    commands: &'static [&'static str]
+}
+
+static OPTIONS : &[OptionDef] = &[
+    OptionDef { name: "--scale", value: Some("<n>"), commands: &["run"],
+        help: "Window size as a multiple of 256x240, 1 to 8 (default 2)" },
+    OptionDef { name: "--region", value: Some("<ntsc|pal|dendy>"), commands: &["run", "headless"],
+        help: "Run the cart as this region, whatever its header says" },
+    OptionDef { name: "--fast-ppu", value: None, commands: &["run", "headless", "test"],
+        help: "Draw whole scanlines at once.  Quicker, but mid-line effects are lost" },
+    OptionDef { name: "--no-sprite-limit", value: None, commands: &["run", "headless"],
+        help: "Draw every sprite on a line, not just the first 8, to stop flicker" },
+    OptionDef { name: "--palette", value: Some("<file>"), commands: &["run", "headless"],
+        help: "Take the colours from a .pal file" },
+    OptionDef { name: "--save-dir", value: Some("<dir>"), commands: &["run"],
+        help: "Keep battery saves here rather than next to the ROM" },
+    OptionDef { name: "--keys", value: Some("<file>"), commands: &["run"],
+        help: "Read key bindings from a file of \"button = key\" lines" },
+    OptionDef { name: "--debug", value: None, commands: &["run"],
+        help: "Start in the debugger" },
+    OptionDef { name: "--trace", value: Some("<file>"), commands: &["run", "headless"],
+        help: "Log every instruction, in the format of nestest.log" },
+    OptionDef { name: "--patch", value: Some("<file>"), commands: &["run", "headless"],
+        help: "Apply an IPS, UPS or BPS patch to the ROM" },
+    OptionDef { name: "--movie", value: Some("<file>"), commands: &["run", "headless"],
+        help: "Play back the input recorded in an .fm2 movie" },
+    OptionDef { name: "--frames", value: Some("<n>"), commands: &["run", "headless", "test"],
+        help: "Stop after this many frames" },
+    OptionDef { name: "--screenshot", value: Some("<file>"), commands: &["run", "headless"],
+        help: "Save the last frame as a .ppm when exiting" },
+    OptionDef { name: "--json", value: None, commands: &["info"],
+        help: "Print one line of JSON per ROM" },
+];
+
+pub struct Options {
+    pub command: &'static str,
+    pub files: Vec<String>,
+
+    pub scale: u32,
+    pub region: Option<u8>,
+    pub fast_ppu: bool,
+    pub no_sprite_limit: bool,
+    pub palette: Option<String>,
+    pub save_dir: Option<String>,
+    pub keys: Option<String>,
+    pub debug: bool,
+    pub trace: Option<String>,
+    pub patch: Option<String>,
+    pub movie: Option<String>,
+    pub frames: Option<usize>,
+    pub screenshot: Option<String>,
+    pub json: bool
+}
+
+impl Options {
+    fn new(command: &'static str) -> Options {
+        Options {
+            command: command,
+            files: Vec::new(),
+            scale: 2,
+            region: None,
+            fast_ppu: false,
+            no_sprite_limit: false,
+            palette: None,
+            save_dir: None,
+            keys: None,
+            debug: false,
+            trace: None,
+            patch: None,
+            movie: None,
+            frames: None,
+            screenshot: None,
+            json: false
+        }
+    }
+
+    fn set(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        match name {
+            "--scale" => self.scale = match value.and_then(|v| v.parse().ok()) {
+                Some(scale) if (1..9).contains(&scale) => scale,
+                _ => return Err(String::from("--scale takes a number from 1 to 8"))
+            },
+            "--region" => self.region = match value.map(|v| v.to_lowercase()) {
+                Some(ref r) if r == "ntsc" => Some(region::NTSC),
+                Some(ref r) if r == "pal" => Some(region::PAL),
+                Some(ref r) if r == "dendy" => Some(region::DENDY),
+                _ => return Err(String::from("--region takes ntsc, pal or dendy"))
+            },
+            "--frames" => self.frames = match value.and_then(|v| v.parse().ok()) {
+                Some(frames) if frames > 0 => Some(frames),
+                _ => return Err(String::from("--frames takes a number of frames"))
+            },
+            "--palette" => self.palette = value,
+            "--save-dir" => self.save_dir = value,
+            "--keys" => self.keys = value,
+            "--trace" => self.trace = value,
+            "--patch" => self.patch = value,
+            "--movie" => self.movie = value,
+            "--screenshot" => self.screenshot = value,
+            "--fast-ppu" => self.fast_ppu = true,
+            "--no-sprite-limit" => self.no_sprite_limit = true,
+            "--debug" => self.debug = true,
+            "--json" => self.json = true,
+            _ => unreachable!()
+        }
+
+        Ok(())
+    }
+}
+
+// Reads "[command] <files> [options]".  Without a command, the first
+// argument is a ROM to run.
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let (command, rest) = match args.first().and_then(|a| COMMANDS.iter().find(|c| c.name == a)) {
+        Some(command) => (command, &args[1..]),
+        None => (&COMMANDS[0], args)
+    };
+
+    let mut options = Options::new(command.name);
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if !arg.starts_with("--") {
+            options.files.push(arg.clone());
+            continue;
+        }
+
+        let def = match OPTIONS.iter().find(|o| o.name == arg) {
+            Some(def) if def.commands.contains(&command.name) => def,
+            Some(_) => return Err(format!("{} doesn't take {}", command.name, arg)),
+            None => return Err(format!("Unknown option: {}", arg))
+        };
+        let value = match def.value {
+            Some(value) => match rest.next() {
+                Some(v) => Some(v.clone()),
+                None => return Err(format!("{} needs a value: {} {}", arg, arg, value))
+            },
+            None => None
+        };
+        try!(options.set(def.name, value));
+    }
+
+    let many = command.args.ends_with("...");
+    match options.files.len() {
+        0 => return Err(format!("{} needs a ROM", command.name)),
+        1 => {},
+        _ if !many => return Err(format!("{} takes one ROM", command.name)),
+        _ => {}
+    }
+    if command.name == "headless" && options.frames.is_none() && options.movie.is_none() {
+        return Err(String::from("headless needs --frames or --movie to know when to stop"));
+    }
+
+    Ok(options)
+}
+
+pub fn usage() -> String {
+    let mut text = String::from("Usage: rustynes [command] <rom> [options]\n\nCommands:\n");
+
+    let commands : Vec<(String, &str)> = COMMANDS.iter()
+        .map(|c| (format!("{} {}", c.name, c.args), c.help))
+        .collect();
+    let options : Vec<(String, String)> = OPTIONS.iter()
+        .map(|o| (match o.value {
+            Some(value) => format!("{} {}", o.name, value),
+            None => String::from(o.name)
+        }, format!("{} ({})", o.help, o.commands.join(", "))))
+        .collect();
+
+    let width = commands.iter().map(|c| c.0.len()).chain(options.iter().map(|o| o.0.len())).max().unwrap_or(0);
+
+    for (usage, help) in &commands {
+        text.push_str(&format!("  {:<width$}  {}\n", usage, help, width = width));
+    }
+    text.push_str("\nOptions:\n");
+    for (usage, help) in &options {
+        text.push_str(&format!("  {:<width$}  {}\n", usage, help, width = width));
+    }
+
+    text
+}
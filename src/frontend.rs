@@ -0,0 +1,650 @@
+use sdl2;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::collections::VecDeque;
+use std::thread::sleep;
+use std::time::Duration;
+
+use rustynes::cpu::BreakCondition;
+use rustynes::opcodes::disassemble;
+use rustynes::cart::region;
+use rustynes::mmu::Mmu;
+use rustynes::joypad::button;
+use rustynes::nes::{Nes, SCREEN_WIDTH};
+use rustynes::trace::Tracer;
+
+use cli::Options;
+use load_movie;
+
+const VISIBLE_WIDTH: u32 = 256;
+const VISIBLE_HEIGHT: u32 = 240;
+
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+const AUDIO_BUFFER_SAMPLES: u16 = 1024;
+
+// How far the resampling ratio may stray from nominal to correct the queue depth
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// Used to pace frames when no audio device is available
+const MS_PER_FRAME: f64 = 1000.0 / 60.0988;
+const MS_PER_FRAME_PAL: f64 = 1000.0 / 50.007;
+
+#[derive(Clone)]
+enum DebuggerCommand {
+    RunCpuUntil(BreakCondition),
+    ToggleShowCpu,
+    ToggleShowMem,
+    ToggleDebug,
+    ToggleTrace(String),
+    ShowPpu,
+    ShowCart,
+    PrintAddr(u16, u16),
+    PrintPpuAddr(u16, u16),
+    Disassemble(u16, usize),
+    Nop,
+    Ppm,
+    Quit
+}
+
+// Samples produced by the APU wait here until SDL asks for them
+struct AudioQueue {
+    samples: VecDeque<i16>,
+    last_sample: i16
+}
+
+impl AudioCallback for AudioQueue {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        for x in out.iter_mut() {
+            // On underrun, hold the last sample rather than dropping to 0 to avoid a pop
+            if let Some(sample) = self.samples.pop_front() {
+                self.last_sample = sample;
+            }
+            *x = self.last_sample;
+        }
+    }
+}
+
+// Paces emulation to the audio device when there is one, otherwise to the
+// SDL timer
+struct FramePacer {
+    audio_device: Option<AudioDevice<AudioQueue>>,
+    target_queue_depth: usize,
+    ms_per_frame: f64,
+    next_frame_ticks: f64
+}
+
+impl FramePacer {
+    fn new(sdl_context: &sdl2::Sdl, nes: &mut Nes) -> FramePacer {
+        let ms_per_frame = match nes.mmu.mapper.cart().header.region {
+            region::PAL | region::DENDY => MS_PER_FRAME_PAL,
+            _ => MS_PER_FRAME
+        };
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(AUDIO_SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(AUDIO_BUFFER_SAMPLES)
+        };
+
+        let mut obtained_freq = AUDIO_SAMPLE_RATE;
+        let mut obtained_samples = AUDIO_BUFFER_SAMPLES;
+        let audio_device = sdl_context.audio().and_then(|audio_subsystem| {
+            audio_subsystem.open_playback(None, desired_spec, |spec| {
+                obtained_freq = spec.freq;
+                obtained_samples = spec.samples;
+                AudioQueue { samples: VecDeque::new(), last_sample: 0 }
+            })
+        });
+
+        match audio_device {
+            Ok(device) => {
+                nes.set_sample_rate(obtained_freq as u32);
+                device.resume();
+                FramePacer {
+                    audio_device: Some(device),
+                    target_queue_depth: 2 * (obtained_samples as usize),
+                    ms_per_frame: ms_per_frame,
+                    next_frame_ticks: 0.0
+                }
+            },
+            Err(e) => {
+                println!("Audio unavailable, running without sound: {}", e);
+                FramePacer { audio_device: None, target_queue_depth: 0, ms_per_frame: ms_per_frame, next_frame_ticks: 0.0 }
+            }
+        }
+    }
+
+    fn wait_for_next_frame(&mut self, nes: &mut Nes, timer: &mut sdl2::TimerSubsystem) {
+        let samples = nes.audio_samples();
+
+        match self.audio_device {
+            Some(ref mut device) => {
+                let mut queue_depth = {
+                    let mut queue = device.lock();
+                    queue.samples.extend(samples.iter());
+                    queue.samples.len()
+                };
+
+                // Nudge the resampling ratio: produce slightly fewer samples
+                // while the queue is above its target and more while below
+                let fill = (queue_depth as f64 / self.target_queue_depth as f64).min(2.0);
+                nes.mmu.apu.set_rate_adjust(1.0 + MAX_RATE_DELTA * (fill - 1.0));
+
+                while queue_depth > self.target_queue_depth {
+                    sleep(Duration::from_millis(1));
+                    queue_depth = device.lock().samples.len();
+                }
+            },
+            None => {
+                let now = timer.ticks() as f64;
+                self.next_frame_ticks += self.ms_per_frame;
+                if self.next_frame_ticks > now {
+                    sleep(Duration::from_millis((self.next_frame_ticks - now) as u64));
+                }
+                else if (now - self.next_frame_ticks) > 100.0 {
+                    // We've fallen far behind (eg, sitting in the debugger), so resync
+                    self.next_frame_ticks = now;
+                }
+            }
+        }
+    }
+}
+
+pub fn output_ppm(nes: &Nes, frame: usize) -> Result<(), io::Error> {
+    let fname = format!("screens\\outputfile_{}.ppm", frame);
+    let mut f = try!(File::create(fname));
+
+    nes.write_ppm(&mut f)
+}
+
+fn prompt(prev_command: DebuggerCommand, info: &String) -> Result<DebuggerCommand, io::Error> {
+    loop {
+        print!("{}> ", info);
+        try!(io::stdout().flush());
+        
+        let mut buffer = String::new();
+        try!(io::stdin().read_line(&mut buffer));
+        
+        for line in buffer.lines() {
+            let parts : Vec<&str> = line.split_whitespace().collect();
+            
+            if parts.len() == 0 {
+                return Ok(prev_command);
+            }
+            
+            match parts[0] {
+                "quit" | "q" => return Ok(DebuggerCommand::Quit),
+                "cpu" => return Ok(DebuggerCommand::ToggleShowCpu),
+                "mem" => return Ok(DebuggerCommand::ToggleShowMem),
+                "ppu" => return Ok(DebuggerCommand::ShowPpu),
+                "cart" => return Ok(DebuggerCommand::ShowCart),
+                "debug" => return Ok(DebuggerCommand::ToggleDebug),
+                "trace" | "t" => {
+                    if parts.len() == 1 {
+                        return Ok(DebuggerCommand::ToggleTrace(String::from("trace.log")));
+                    }
+                    else {
+                        return Ok(DebuggerCommand::ToggleTrace(String::from(parts[1])));
+                    }
+                },
+                "ppm" => return Ok(DebuggerCommand::Ppm),
+                "frame" | "fr" => {
+                    if parts.len() == 1 {
+                        return Ok(DebuggerCommand::RunCpuUntil(BreakCondition::RunFrame));
+                    }
+                    else {
+                        let toframe = parts[1];
+                        match usize::from_str_radix(toframe, 10) {
+                            Ok(val) => return Ok(DebuggerCommand::RunCpuUntil(BreakCondition::RunUntilFrame(val))),
+                            _ => println!("Supply a frame to break on. Eg: frame 100")
+                        }
+                    }
+                },
+                "sl" => return Ok(DebuggerCommand::RunCpuUntil(BreakCondition::RunToScanline)),
+                "next" | "n" => return Ok(DebuggerCommand::RunCpuUntil(BreakCondition::RunNext)),
+                "break" | "br" => {
+                        if parts.len() < 2 {
+                            println!("Supply a PC to break on. Eg: break fffc");
+                        }
+                        else {
+                            let pc = parts[1];
+                            match u16::from_str_radix(pc, 16) {
+                                Ok(val) => return Ok(DebuggerCommand::RunCpuUntil(BreakCondition::RunToPc(val))),
+                                _ => println!("Supply a PC to break on. Eg: break fffc")
+                            }
+                        }
+                    },
+                "print" | "p" => {
+                        if parts.len() < 2 {
+                            println!("Supply an address to show. Eg: print fffc");
+                        }
+                        else {
+                            let start = parts[1];
+                            match u16::from_str_radix(start, 16) {
+                                Ok(val) => {
+                                    if parts.len() == 3 {
+                                        match u16::from_str_radix(parts[2], 16) {
+                                            Ok(val2) => return Ok(DebuggerCommand::PrintAddr(val, val2)),
+                                            _ => println!("Supply an end address to show. Eg: print fffc fffe")
+                                        }
+                                    }
+                                    else if parts.len() == 2 {
+                                        return Ok(DebuggerCommand::PrintAddr(val, val));                                    
+                                    }
+                                    else {
+                                        println!("Too many arguments to print command");
+                                    }
+                                },
+                                _ => println!("Supply an address to show. Eg: print fffc")
+                            }                            
+                        }
+                    },
+                "printppu" | "pp" => {
+                        if parts.len() < 2 {
+                            println!("Supply an address to show. Eg: print fffc");
+                        }
+                        else {
+                            let start = parts[1];
+                            match u16::from_str_radix(start, 16) {
+                                Ok(val) => {
+                                    if parts.len() == 3 {
+                                        match u16::from_str_radix(parts[2], 16) {
+                                            Ok(val2) => return Ok(DebuggerCommand::PrintPpuAddr(val, val2)),
+                                            _ => println!("Supply an end address to show. Eg: print fffc fffe")
+                                        }
+                                    }
+                                    else if parts.len() == 2 {
+                                        return Ok(DebuggerCommand::PrintPpuAddr(val, val));                                    
+                                    }
+                                    else {
+                                        println!("Too many arguments to print command");
+                                    }
+                                },
+                                _ => println!("Supply an address to show. Eg: print fffc")
+                            }                            
+                        }
+                    },
+                "dis" | "d" => {
+                        if parts.len() < 2 {
+                            println!("Supply an address to disassemble. Eg: dis c000 10");
+                        }
+                        else {
+                            match u16::from_str_radix(parts[1], 16) {
+                                Ok(val) => {
+                                    if parts.len() == 3 {
+                                        match usize::from_str_radix(parts[2], 10) {
+                                            Ok(count) => return Ok(DebuggerCommand::Disassemble(val, count)),
+                                            _ => println!("Supply a number of instructions. Eg: dis c000 10")
+                                        }
+                                    }
+                                    else if parts.len() == 2 {
+                                        return Ok(DebuggerCommand::Disassemble(val, 10));
+                                    }
+                                    else {
+                                        println!("Too many arguments to dis command");
+                                    }
+                                },
+                                _ => println!("Supply an address to disassemble. Eg: dis c000 10")
+                            }
+                        }
+                    },
+                "help" | "h" => {
+                    println!("Commands available:");
+                    println!("  q(uit): leave debugger");
+                    println!("  cpu: toggle showing cpu contents");
+                    println!("  mem: toggle showing mem contents");
+                    println!("  debug: toggle cpu verbose debug");
+                    println!("  t(race) (<file>): toggle nestest-style trace log, to trace.log by default");
+                    println!("  ppu: show ppu contents");
+                    println!("  cart: show the cart's header");
+                    println!("  fr(ame) (<num>): run until next video frame or #num");
+                    println!("  br(eak) <addr>: run until pc == addr");
+                    println!("  sl: run until next scanline");
+                    println!("  n(ext): run until next instruction");
+                    println!("  p(rint) <addr> (<end addr>): show memory at addr");
+                    println!("  pp <addr> (<end addr>): show ppu memory at addr");
+                    println!("  d(is) <addr> (<count>): disassemble count instructions at addr");
+                    println!("  ppm: save ppm of current video frame to 'screens'");
+                },
+                _ => println!("Use 'help' to see commands")
+            }
+        }
+    }
+}
+
+fn print_addr(mmu: &mut Mmu, addr1: u16, addr2: u16) {
+    let mut idx = 0;
+    
+    loop {
+        if idx % 16 == 0 {
+            print!("{0:04x}: ", addr1 + idx);
+        }
+        print!("{0:02x} ", mmu.read_u8(addr1 + idx));
+        
+        if (addr1 + idx) == addr2 {
+            break;
+        }
+        
+        idx += 1;
+        
+        if (idx % 16) == 0 {
+            println!("");
+        }
+    }
+    println!("");
+}
+
+fn print_ppu_addr(mmu: &mut Mmu, addr1: u16, addr2: u16) {
+    let mut idx = 0;
+    
+    loop {
+        if idx % 16 == 0 {
+            print!("{0:04x}: ", addr1 + idx);
+        }
+        if ((addr1 + idx) >= 0x2000) && ((addr1 + idx) < 0x4000) {
+            print!("{0:02x} ", mmu.ppu.name_tables[(addr1 as usize) - 0x2000 + (idx as usize)]);
+        }
+        else if (addr1 + idx) < 0x2000 {
+            print!("{0:02x} ", mmu.mapper.ppu_read(addr1 + idx));
+        }
+        if (addr1 + idx) == addr2 {
+            break;
+        }
+        
+        idx += 1;
+        
+        if (idx % 16) == 0 {
+            println!("");
+        }
+    }
+    println!("");
+}
+
+fn print_disassembly(mmu: &mut Mmu, addr: u16, count: usize) {
+    let mut pc = addr;
+    
+    for _ in 0..count {
+        let (text, length) = disassemble(mmu, pc);
+        println!("{0:04x}: {1}", pc, text);
+        pc = pc.wrapping_add(length);
+    }
+}
+
+type KeyMap = Vec<(Keycode, u8)>;
+
+fn default_key_map() -> KeyMap {
+    vec![
+        (Keycode::Z, button::A),
+        (Keycode::X, button::B),
+        (Keycode::A, button::SELECT),
+        (Keycode::S, button::START),
+        (Keycode::Up, button::UP),
+        (Keycode::Down, button::DOWN),
+        (Keycode::Left, button::LEFT),
+        (Keycode::Right, button::RIGHT)
+    ]
+}
+
+// Reads key bindings, a "button = key" a line, eg: "start = Return".  Keys
+// go by their SDL names.  Buttons that aren't mentioned keep their keys.
+fn load_key_map(fname: &str) -> Result<KeyMap, io::Error> {
+    use std::io::{Error, ErrorKind};
+
+    let mut text = String::new();
+    try!(try!(File::open(fname)).read_to_string(&mut text));
+
+    let mut key_map = default_key_map();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bad_line = |reason: &str| Error::new(ErrorKind::InvalidData,
+            format!("{} line {}: {}", fname, number + 1, reason));
+
+        let mut parts = line.splitn(2, '=').map(|p| p.trim());
+        let (name, key_name) = match (parts.next(), parts.next()) {
+            (Some(name), Some(key_name)) => (name, key_name),
+            _ => return Err(bad_line("expected \"button = key\""))
+        };
+        let button = match &*name.to_lowercase() {
+            "a" => button::A,
+            "b" => button::B,
+            "select" => button::SELECT,
+            "start" => button::START,
+            "up" => button::UP,
+            "down" => button::DOWN,
+            "left" => button::LEFT,
+            "right" => button::RIGHT,
+            _ => return Err(bad_line(&format!("no button called {}", name)))
+        };
+        let key = match Keycode::from_name(key_name) {
+            Some(key) => key,
+            None => return Err(bad_line(&format!("no key called {}", key_name)))
+        };
+
+        key_map.retain(|&(_, b)| b != button);
+        key_map.push((key, button));
+    }
+
+    Ok(key_map)
+}
+
+fn buttons_from_keys(keys: &[Keycode], key_map: &[(Keycode, u8)]) -> u8 {
+    let mut buttons = 0;
+    for &(key, button) in key_map.iter() {
+        if keys.contains(&key) {
+            buttons |= button;
+        }
+    }
+    
+    buttons
+}
+
+fn draw_frame_and_pump_events(nes: &mut Nes, renderer: &mut sdl2::render::Renderer, texture: &mut sdl2::render::Texture,
+    event_pump: &mut sdl2::EventPump, key_map: &[(Keycode, u8)], scale: u32) -> bool {
+    
+    let framebuffer = nes.framebuffer();
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for row in 0..(VISIBLE_HEIGHT as usize) {
+            for col in 0..(VISIBLE_WIDTH as usize) {
+                let pixel = framebuffer[row * SCREEN_WIDTH + col];
+                let offset = row*pitch + col*3;
+
+                buffer[offset] = (pixel >> 16) as u8;
+                buffer[offset + 1] = ((pixel >> 8) & 0xff) as u8;
+                buffer[offset + 2] = (pixel & 0xff) as u8;
+            }
+        }
+    }).unwrap();
+
+    // The renderer does the scaling
+    renderer.clear();
+    renderer.copy(&texture, None, Some(Rect::new_unwrap(0, 0, VISIBLE_WIDTH * scale, VISIBLE_HEIGHT * scale)));
+    renderer.present();
+    
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => 
+                return true,
+            _ => ()
+        }
+    }
+    
+    let keys : Vec<Keycode> = event_pump.keyboard_state().pressed_scancodes().
+        filter_map(Keycode::from_scancode).collect();
+
+    nes.set_buttons(0, buttons_from_keys(&keys, key_map));
+    
+    false
+}
+
+fn wait_for_exit(nes: &mut Nes, renderer: &mut sdl2::render::Renderer, texture: &mut sdl2::render::Texture,
+    event_pump: &mut sdl2::EventPump, scale: u32) {
+    
+    while !draw_frame_and_pump_events(nes, renderer, texture, event_pump, &[], scale) {
+        sleep(Duration::from_millis(MS_PER_FRAME as u64));
+    }
+}
+
+// Plays a loaded cart in a window until it's closed, Escape is pressed or
+// the frame limit is reached
+pub fn run_cart(mut nes: Nes, options: &Options) -> Result<(), io::Error> {
+    use std::cmp;
+    
+    let key_map = match options.keys {
+        Some(ref fname) => try!(load_key_map(fname)),
+        None => default_key_map()
+    };
+    let movie = try!(load_movie(options));
+    let scale = options.scale;
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+
+    let window = video_subsystem.window("rustynes", VISIBLE_WIDTH * scale, VISIBLE_HEIGHT * scale)
+        .position_centered()
+        .opengl()
+        .build()
+        .unwrap();
+
+    let mut renderer = window.renderer().build().unwrap();
+
+    let mut texture = renderer.create_texture_streaming(PixelFormatEnum::RGB24, (VISIBLE_WIDTH, VISIBLE_HEIGHT)).unwrap();
+    
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut timer = sdl_context.timer().unwrap();
+    
+    let mut pacer = FramePacer::new(&sdl_context, &mut nes);
+
+    let mut debug_info : String;
+    let mut show_cpu = true;
+    let mut show_mem = false;
+    let mut prev_command = DebuggerCommand::Nop;
+
+    if !options.debug {
+        'gameloop: loop {
+            if options.frames.is_some_and(|limit| nes.frame_count >= limit) {
+                break 'gameloop
+            }
+            // The movie's input wins over the keyboard's until it runs out
+            if let Some(ref movie) = movie {
+                movie.play_frame(&mut nes);
+            }
+
+            match nes.step_frame() {
+                Ok(_) => {},
+                Err(e) => {
+                    // Like the real hardware, sit there frozen until the player gives up
+                    println!("{}.  Halting, close the window to exit", e);
+                    wait_for_exit(&mut nes, &mut renderer, &mut texture, &mut event_pump, scale);
+                    break 'gameloop
+                }
+            }
+
+            let exiting = draw_frame_and_pump_events(&mut nes, &mut renderer, &mut texture, &mut event_pump, &key_map, scale);
+            if exiting { break 'gameloop }
+            pacer.wait_for_next_frame(&mut nes, &mut timer);
+        }
+    }
+    else {
+        let mut cond_met;
+        'gameloop_debug: loop {
+            if show_cpu {
+                nes.cpu.fetch(&mut nes.mmu);
+                debug_info = format!("[{:?}] {}", nes.cpu, disassemble(&mut nes.mmu, nes.cpu.pc).0);
+            }
+            else {
+                debug_info = String::new();
+            }
+            
+            if show_mem {
+                let pc = nes.cpu.pc;
+                print_addr(&mut nes.mmu, pc, pc + cmp::min(5, 0xffff - pc));
+            }
+            
+            // Leave through the bottom, so save RAM is still written
+            let command = match prompt(prev_command, &debug_info) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    break
+                }
+            };
+            prev_command = command.clone();
+            match command {
+                DebuggerCommand::Quit => break,
+                DebuggerCommand::Nop => {},
+                DebuggerCommand::Ppm => if let Err(e) = output_ppm(&nes, nes.frame_count) {
+                    println!("Could not write the screen: {}", e);
+                },
+                DebuggerCommand::ShowPpu => println!("{:?}", nes.mmu.ppu),
+                DebuggerCommand::ShowCart => println!("{}", nes.mmu.mapper.cart().header),
+                DebuggerCommand::ToggleShowCpu => show_cpu = !show_cpu,
+                DebuggerCommand::ToggleShowMem => show_mem = !show_mem,
+                DebuggerCommand::PrintAddr(addr1, addr2) => print_addr(&mut nes.mmu, addr1, addr2),
+                DebuggerCommand::PrintPpuAddr(addr1, addr2) => print_ppu_addr(&mut nes.mmu, addr1, addr2),
+                DebuggerCommand::Disassemble(addr, count) => print_disassembly(&mut nes.mmu, addr, count),
+                DebuggerCommand::ToggleDebug => nes.cpu.is_debugging = !nes.cpu.is_debugging,
+                DebuggerCommand::ToggleTrace(trace_fname) => {
+                    if nes.cpu.tracer.is_some() {
+                        nes.cpu.tracer = None;
+                        println!("Tracing stopped");
+                    }
+                    else {
+                        match Tracer::new(&trace_fname) {
+                            Ok(tracer) => {
+                                nes.cpu.tracer = Some(tracer);
+                                println!("Tracing to {}", trace_fname);
+                            },
+                            Err(e) => println!("Could not trace to {}: {}", trace_fname, e)
+                        }
+                    }
+                },
+                DebuggerCommand::RunCpuUntil(cond) => {
+                    cond_met = false;
+                    while !cond_met {
+                        match nes.cpu.run_until_condition(&mut nes.mmu, &cond) {
+                            Ok(met) => cond_met = met,
+                            Err(e) => {
+                                // Hand control back to the prompt at the faulting pc
+                                println!("{}", e);
+                                break;
+                            }
+                        }
+                        
+                        if nes.cpu.is_scanline_done(&nes.mmu) {
+                            nes.end_scanline();
+                            
+                            if nes.is_frame_complete() {
+                                let exiting = draw_frame_and_pump_events(&mut nes, &mut renderer, &mut texture, &mut event_pump,
+                                    &key_map, scale);
+                                if exiting { break 'gameloop_debug }
+                                
+                                pacer.wait_for_next_frame(&mut nes, &mut timer);
+    
+                                match cond {
+                                    BreakCondition::RunFrame => cond_met = true,
+                                    BreakCondition::RunUntilFrame(f) => if nes.frame_count == f { cond_met = true; },
+                                    _ => {}
+                                }                            
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    nes.shut_down(options.screenshot.as_ref())
+}
@@ -0,0 +1,99 @@
+use crc32fast::Hasher;
+use sha1_smol::Sha1;
+
+use cart::{region, CartHeader};
+use ppu::mirroring;
+
+// Flags for per-game behaviour that no header can describe.  None are
+// needed since the scroll registers were emulated properly, but any a
+// game turns out to need belong here rather than in title checks.
+pub mod quirk {
+}
+
+// Of PRG followed by CHR, without the header or trainer
+pub enum Key {
+    Crc32(u32),
+    Sha1(&'static str)
+}
+
+// What a game's header should have said, and the quirks it needs.  None
+// leaves the header's value alone.
+pub struct Game {
+    pub key: Key,
+    pub name: &'static str,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<u8>,
+    pub battery: Option<bool>,
+    pub region: Option<u8>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub quirks: u8
+}
+
+// An entry that changes nothing, to build the others from
+pub const BLANK : Game = Game {
+    key: Key::Crc32(0),
+    name: "",
+    mapper: None,
+    submapper: None,
+    mirroring: None,
+    battery: None,
+    region: None,
+    prg_ram_size: None,
+    prg_nvram_size: None,
+    chr_ram_size: None,
+    quirks: 0
+};
+
+// Keyed by the hash of the ROM data alone, so an entry puts right any
+// header a dump of it turns up with.  Hashes are No-Intro's.  Junk left
+// in old headers is dealt with by CartHeader::parse, not per game.
+pub static GAMES : &[Game] = &[
+    Game { key: Key::Sha1("ea343f4e445a9050d4b4fbac2c77d0693b1d0922"), name: "Super Mario Bros. (World)",
+        mapper: Some(0), mirroring: Some(mirroring::VERTICAL), battery: Some(false), region: Some(region::NTSC),
+        chr_ram_size: Some(0), ..BLANK },
+];
+
+pub fn crc32(prg: &[u8], chr: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(prg);
+    hasher.update(chr);
+    hasher.finalize()
+}
+
+pub fn sha1(prg: &[u8], chr: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(prg);
+    hasher.update(chr);
+    hasher.digest().to_string()
+}
+
+pub fn find(prg: &[u8], chr: &[u8]) -> Option<&'static Game> {
+    find_in(GAMES, prg, chr)
+}
+
+pub fn find_in<'a>(games: &'a [Game], prg: &[u8], chr: &[u8]) -> Option<&'a Game> {
+    let crc = crc32(prg, chr);
+    let sha = sha1(prg, chr);
+
+    games.iter().find(|game| match game.key {
+        Key::Crc32(c) => c == crc,
+        Key::Sha1(s) => s.eq_ignore_ascii_case(&sha)
+    })
+}
+
+impl Game {
+    pub fn apply(&self, header: &mut CartHeader) {
+        if let Some(mapper) = self.mapper { header.mapper = mapper; }
+        if let Some(submapper) = self.submapper { header.submapper = submapper; }
+        if let Some(mirroring) = self.mirroring { header.mirroring = mirroring; }
+        if let Some(battery) = self.battery { header.battery = battery; }
+        if let Some(region) = self.region { header.region = region; }
+        if let Some(size) = self.prg_ram_size { header.prg_ram_size = size; }
+        if let Some(size) = self.prg_nvram_size { header.prg_nvram_size = size; }
+        if let Some(size) = self.chr_ram_size { header.chr_ram_size = size; }
+        header.quirks = self.quirks;
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use cart::{CartHeader, LoadError, RomImage, read_image, read_patched_rom_file};
+use gamedb;
+use gamedb::Game;
+use mapper;
+
+// What the loader makes of a ROM file, for `rustynes info`.  The header
+// is as the file has it, the database match says what gets corrected.
+pub struct RomInfo {
+    pub file: String,
+    pub header: CartHeader,
+    pub crc32: u32,
+    pub sha1: String,
+    pub game: Option<&'static Game>
+}
+
+impl RomInfo {
+    // Reads the file as load_cart would, unpacked and patched
+    pub fn read(fname: &str) -> Result<RomInfo, LoadError> {
+        let (rom, _) = try!(read_patched_rom_file(fname, None));
+        RomInfo::from_image(fname, &rom)
+    }
+
+    pub fn from_image(fname: &str, data: &[u8]) -> Result<RomInfo, LoadError> {
+        let RomImage { header, prg_rom, chr_rom, .. } = try!(read_image(&mut &data[..]));
+
+        Ok(RomInfo {
+            file: String::from(fname),
+            header: header,
+            crc32: gamedb::crc32(&prg_rom, &chr_rom),
+            sha1: gamedb::sha1(&prg_rom, &chr_rom),
+            game: gamedb::find(&prg_rom, &chr_rom)
+        })
+    }
+
+    // One line of JSON, so a library's worth can be streamed to a script
+    pub fn to_json(&self) -> String {
+        let header = &self.header;
+        let fields = vec![
+            ("file", json_string(&self.file)),
+            ("format", json_string(header.format_name())),
+            ("board", header.board.as_ref().map_or(String::from("null"), |b| json_string(b))),
+            ("name", header.name.as_ref().map_or(String::from("null"), |n| json_string(n))),
+            ("mapper", header.mapper.to_string()),
+            ("mapper_name", mapper::name(header.mapper).map_or(String::from("null"), json_string)),
+            ("submapper", header.submapper.to_string()),
+            ("prg_rom_size", header.prg_rom_size.to_string()),
+            ("chr_rom_size", header.chr_rom_size.to_string()),
+            ("prg_ram_size", header.prg_ram_size.to_string()),
+            ("prg_nvram_size", header.prg_nvram_size.to_string()),
+            ("chr_ram_size", header.chr_ram_size.to_string()),
+            ("chr_nvram_size", header.chr_nvram_size.to_string()),
+            ("mirroring", json_string(header.mirroring_name())),
+            ("battery", header.battery.to_string()),
+            ("trainer", header.trainer.to_string()),
+            ("region", json_string(header.region_name())),
+            ("crc32", json_string(&format!("{:08x}", self.crc32))),
+            ("sha1", json_string(&self.sha1)),
+            ("database", self.game.map_or(String::from("null"), |g| json_string(g.name)))
+        ];
+
+        json_object(&fields)
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "File: {}", self.file));
+        try!(writeln!(f, "{}", self.header));
+        try!(writeln!(f, "CRC32: {:08x}", self.crc32));
+        try!(writeln!(f, "SHA-1: {}", self.sha1));
+        write!(f, "Database: {}", match self.game {
+            Some(game) => game.name,
+            None => "no match"
+        })
+    }
+}
+
+// The JSON line for a file that couldn't be read
+pub fn error_json(fname: &str, error: &LoadError) -> String {
+    json_object(&[("file", json_string(fname)), ("error", json_string(&error.to_string()))])
+}
+
+fn json_object(fields: &[(&str, String)]) -> String {
+    let members : Vec<String> = fields.iter()
+        .map(|&(name, ref value)| format!("\"{}\":{}", name, value))
+        .collect();
+
+    format!("{{{}}}", members.join(","))
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
@@ -1,49 +1,68 @@
-use sdl2::keyboard::Keycode;
+pub mod button {
+    pub const A      : u8 = 0x01;
+    pub const B      : u8 = 0x02;
+    pub const SELECT : u8 = 0x04;
+    pub const START  : u8 = 0x08;
+    pub const UP     : u8 = 0x10;
+    pub const DOWN   : u8 = 0x20;
+    pub const LEFT   : u8 = 0x40;
+    pub const RIGHT  : u8 = 0x80;
+}
 
 pub struct Joypad {
-    keys: Vec<Keycode>,
+    buttons: [u8; 2],
     joypad_1_last_write: u8,
-    joypad_1_read_ptr: u8
+    read_ptr: [u8; 2]
 }
 
 impl Joypad {
     pub fn new() -> Joypad { 
-        Joypad { keys: Vec::new(), joypad_1_last_write: 0,
-            joypad_1_read_ptr: 0 }
+        Joypad { buttons: [0; 2], joypad_1_last_write: 0,
+            read_ptr: [0; 2] }
     }
     
-    pub fn update_keys(&mut self, keys: Vec<Keycode>) {
-        self.keys = keys;
+    // Takes the held buttons as a mask of button flags.  Port 0 is the
+    // first controller.
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.buttons[port] = buttons;
     }
     
-    pub fn joypad_1_read(&mut self) -> u8 {
+    fn is_pressed(&self, port: usize, button: u8) -> bool {
+        (self.buttons[port] & button) == button
+    }
+    
+    fn read(&mut self, port: usize) -> u8 {
         let result = 
-            match self.joypad_1_read_ptr {
-                1 => self.keys.contains(&Keycode::Z),  // A
-                2 => self.keys.contains(&Keycode::X),  // B
-                3 => self.keys.contains(&Keycode::A),  // Select
-                4 => self.keys.contains(&Keycode::S),  // Start
-                5 => self.keys.contains(&Keycode::Up),
-                6 => self.keys.contains(&Keycode::Down) && !self.keys.contains(&Keycode::Up),
-                7 => self.keys.contains(&Keycode::Left),
-                8 => self.keys.contains(&Keycode::Right) && !self.keys.contains(&Keycode::Left),                
+            match self.read_ptr[port] {
+                1 => self.is_pressed(port, button::A),
+                2 => self.is_pressed(port, button::B),
+                3 => self.is_pressed(port, button::SELECT),
+                4 => self.is_pressed(port, button::START),
+                5 => self.is_pressed(port, button::UP),
+                6 => self.is_pressed(port, button::DOWN) && !self.is_pressed(port, button::UP),
+                7 => self.is_pressed(port, button::LEFT),
+                8 => self.is_pressed(port, button::RIGHT) && !self.is_pressed(port, button::LEFT),                
                 _ => false
             };
-        self.joypad_1_read_ptr += 1;
+        if self.read_ptr[port] <= 8 {
+            self.read_ptr[port] += 1;
+        }
         return if result {1} else {0};
     }
     
+    pub fn joypad_1_read(&mut self) -> u8 {
+        self.read(0)
+    }
+    
+    // The strobe written to $4016 latches both controllers
     pub fn joypad_1_write(&mut self, data: u8) {
         if (data == 0) && (self.joypad_1_last_write == 1) {
-            self.joypad_1_read_ptr = 1;
+            self.read_ptr = [1; 2];
         }
         self.joypad_1_last_write = data;
     }
     
-    pub fn joypad_2_read(&self) -> u8 {
-        return 0;
+    pub fn joypad_2_read(&mut self) -> u8 {
+        self.read(1)
     }
-    
-    pub fn joypad_2_write(&mut self, _: u8) {
-    }
-}
\ No newline at end of file
+}
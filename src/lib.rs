@@ -0,0 +1,22 @@
+extern crate crc32fast;
+extern crate sha1_smol;
+extern crate flate2;
+extern crate zip;
+
+mod util;
+pub mod cpu;
+pub mod opcodes;
+pub mod trace;
+pub mod joypad;
+pub mod mmu;
+pub mod cart;
+pub mod mapper;
+pub mod gamedb;
+pub mod patch;
+pub mod info;
+pub mod movie;
+pub mod testrom;
+pub mod ppu;
+pub mod apu;
+pub mod nes;
+pub use nes::Nes;
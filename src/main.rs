@@ -1,29 +1,183 @@
 extern crate sdl2;
+extern crate rustynes;
 
-mod util;
-mod cpu;
-mod joypad;
-mod mmu;
-mod cart;
-mod ppu;
-mod nes;
+mod cli;
+mod frontend;
+
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::process::exit;
+
+use rustynes::Nes;
+use rustynes::cart::{load_cart, read_patched_rom_file};
+use rustynes::movie::Movie;
+use rustynes::ppu::parse_palette;
+use rustynes::testrom;
+use rustynes::trace::Tracer;
+
+use cli::Options;
+
+// How long a test ROM gets without --frames, a minute of emulated time
+const TEST_FRAMES : usize = 60 * 60;
 
 fn main() {
     use std::env::args;
 
     let cmdline_args : Vec<String> = args().skip(1).collect();
-    
-    if cmdline_args.len() == 0 {
-        println!("Usage: rustynes <filename>");
+
+    if cmdline_args.is_empty() || cmdline_args.iter().any(|a| a == "--help" || a == "-h") {
+        print!("{}", cli::usage());
         return;
     }
-    
-    let use_debug = (cmdline_args.len() == 2) && (cmdline_args[1] == "--debug"); 
-    
-    //println!("Loading: {}", &cmdline_args[0]);
-    let result = nes::run_cart(&cmdline_args[0], use_debug);
+
+    let options = match cli::parse(&cmdline_args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, cli::usage());
+            exit(2);
+        }
+    };
+
+    let succeeded = match options.command {
+        "info" => show_info(&options),
+        "test" => run_tests(&options),
+        "headless" => report(run_headless(&options), &options),
+        _ => report(load_nes(&options).and_then(|nes| frontend::run_cart(nes, &options)), &options)
+    };
+
+    if !succeeded {
+        exit(1);
+    }
+}
+
+fn report(result: Result<(), io::Error>, options: &Options) -> bool {
     match result {
-        Ok(_) => {},
-        Err(e) => println!("Error loading: {}.  {}", cmdline_args[0], e)
+        Ok(_) => true,
+        Err(e) => {
+            println!("Error loading: {}.  {}", options.files[0], e);
+            false
+        }
+    }
+}
+
+// Loads the ROM and sets up everything the options ask for around it
+fn load_nes(options: &Options) -> Result<Nes, io::Error> {
+    let mut nes = Nes::new();
+    try!(load_cart(&options.files[0], options.patch.as_ref(), options.save_dir.as_ref(), &mut nes.mmu));
+
+    nes.mmu.ppu.fast_render = options.fast_ppu;
+    nes.mmu.ppu.no_sprite_limit = options.no_sprite_limit;
+    if let Some(region) = options.region {
+        nes.mmu.mapper.cart_mut().header.region = region;
+    }
+    if let Some(ref fname) = options.palette {
+        let mut data = Vec::new();
+        try!(try!(File::open(fname)).read_to_end(&mut data));
+        nes.mmu.ppu.palette = try!(parse_palette(&data));
+    }
+    if let Some(ref fname) = options.trace {
+        nes.cpu.tracer = Some(try!(Tracer::new(fname)));
+    }
+
+    nes.reset();
+    Ok(nes)
+}
+
+pub fn load_movie(options: &Options) -> Result<Option<Movie>, io::Error> {
+    match options.movie {
+        Some(ref fname) => Movie::load(fname).map(Some),
+        None => Ok(None)
     }
 }
+
+pub fn save_screenshot(nes: &Nes, options: &Options) -> Result<(), io::Error> {
+    match options.screenshot {
+        Some(ref fname) => nes.write_ppm(&mut try!(File::create(fname))),
+        None => Ok(())
+    }
+}
+
+// Runs until the frame limit, or the end of the movie if there's no limit
+fn run_headless(options: &Options) -> Result<(), io::Error> {
+    let mut nes = try!(load_nes(options));
+    let movie = try!(load_movie(options));
+
+    loop {
+        if options.frames.is_some_and(|limit| nes.frame_count >= limit) {
+            break;
+        }
+        if let Some(ref movie) = movie {
+            if !movie.play_frame(&mut nes) && options.frames.is_none() {
+                break;
+            }
+        }
+
+        if let Err(e) = nes.step_frame() {
+            println!("{} at frame {}", e, nes.frame_count);
+            break;
+        }
+        // Nothing is listening, so don't let the audio pile up
+        nes.audio_samples();
+    }
+
+    try!(save_screenshot(&nes, options));
+    println!("Ran {} frames", nes.frame_count);
+
+    Ok(())
+}
+
+// Runs each test ROM and prints its result.  Returns false if any failed.
+fn run_tests(options: &Options) -> bool {
+    let frames = options.frames.unwrap_or(TEST_FRAMES);
+    let mut passed = 0;
+
+    for fname in &options.files {
+        let mut nes = Nes::new();
+        let result = read_patched_rom_file(fname, None)
+            .and_then(|(rom, _)| nes.load_rom(&rom))
+            .map(|_| nes.mmu.ppu.fast_render = options.fast_ppu)
+            .map_err(|e| e.to_string())
+            .and_then(|_| testrom::run(&mut nes, frames));
+
+        match result {
+            Ok(text) => {
+                passed += 1;
+                println!("{}: {}", fname, text);
+            },
+            Err(e) => println!("{}: FAILED {}", fname, e)
+        }
+    }
+
+    println!("{} of {} passed", passed, options.files.len());
+    passed == options.files.len()
+}
+
+// Prints what the loader makes of each file.  Returns false if any of
+// them couldn't be read.
+fn show_info(options: &Options) -> bool {
+    use rustynes::info::{RomInfo, error_json};
+
+    let mut all_read = true;
+    for (i, fname) in options.files.iter().enumerate() {
+        match RomInfo::read(fname) {
+            Ok(info) => if options.json { println!("{}", info.to_json()) } else {
+                if i > 0 {
+                    println!();
+                }
+                println!("{}", info);
+            },
+            Err(e) => {
+                all_read = false;
+                if options.json {
+                    println!("{}", error_json(fname, &e));
+                }
+                else {
+                    eprintln!("Error reading: {}.  {}", fname, e);
+                }
+            }
+        }
+    }
+
+    all_read
+}
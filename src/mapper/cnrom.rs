@@ -0,0 +1,22 @@
+use mapper::{Mapper, Cart};
+
+// Any write to ROM picks the 8k of CHR
+pub struct Cnrom {
+    cart: Cart
+}
+
+pub fn new(cart: Cart) -> Box<dyn Mapper> {
+    Box::new(Cnrom { cart: cart })
+}
+
+impl Mapper for Cnrom {
+    fn cart(&self) -> &Cart { &self.cart }
+    fn cart_mut(&mut self) -> &mut Cart { &mut self.cart }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x8000...0xFFFF => self.cart.switch_8k_chr_page(data as usize * 8),
+            _ => self.cart.cpu_write(address, data)
+        }
+    }
+}
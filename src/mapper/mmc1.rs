@@ -0,0 +1,167 @@
+use std::io;
+
+use util::BitReader;
+use mapper::{Mapper, Cart};
+use ppu::mirroring;
+
+// Registers are loaded a bit at a time through a serial port, one per
+// 8k of ROM: control at $8000, CHR banks at $A000 and $C000 and the PRG
+// bank at $E000.  Writing a value with bit 7 set resets the port.
+pub struct Mmc1 {
+    cart: Cart,
+
+    reg_bit: [usize; 4],
+    reg_val: [usize; 4],
+
+    prg_switch_area: u8,
+    prg_switch_size: u8,
+    vrom_switch_size: u8
+}
+
+pub fn new(cart: Cart) -> Box<dyn Mapper> {
+    let mut mmc1 = Mmc1 {
+        cart: cart,
+        reg_bit: [0; 4],
+        reg_val: [0; 4],
+        prg_switch_area: 1,
+        prg_switch_size: 1,
+        vrom_switch_size: 0
+    };
+
+    let num_prg = mmc1.cart.num_prg_pages;
+    mmc1.cart.switch_16k_prg_page((num_prg - 1) * 4, 1);
+
+    Box::new(mmc1)
+}
+
+impl Mmc1 {
+    fn control_write(&mut self, val: usize) {
+        self.cart.mirroring =
+            match val & 0x3 {
+                0 => mirroring::ONE_SCREEN_LOW,
+                1 => mirroring::ONE_SCREEN_HIGH,
+                2 => mirroring::VERTICAL,
+                _ => mirroring::HORIZONTAL
+            };
+
+        self.prg_switch_area = ((val >> 2) & 1) as u8;
+        self.prg_switch_size = ((val >> 3) & 1) as u8;
+        self.vrom_switch_size = ((val >> 4) & 1) as u8;
+
+        self.reg_bit = [0; 4];
+        self.reg_val = [0; 4];
+    }
+
+    fn chr_0_write(&mut self, val: usize) {
+        if self.cart.num_chr_pages > 0 {
+            if self.vrom_switch_size == 1 {
+                self.cart.switch_4k_chr_page(val * 4, 0);
+            }
+            else {
+                self.cart.switch_8k_chr_page((val >> 1) * 8);
+            }
+        }
+    }
+
+    fn chr_1_write(&mut self, val: usize) {
+        if (self.cart.num_chr_pages > 0) && (self.vrom_switch_size == 1) {
+            self.cart.switch_4k_chr_page(val * 4, 1);
+        }
+    }
+
+    fn prg_write(&mut self, val: usize) {
+        let num_prg = self.cart.num_prg_pages;
+
+        if self.prg_switch_size == 1 {
+            if self.prg_switch_area == 1 {
+                self.cart.switch_16k_prg_page(val * 4, 0);
+                self.cart.switch_16k_prg_page((num_prg - 1) * 4, 1);
+            }
+            else {
+                self.cart.switch_16k_prg_page(val * 4, 1);
+                self.cart.switch_16k_prg_page(0, 0);
+            }
+        }
+        else {
+            self.cart.switch_32k_prg_page((val >> 1) * 8);
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cart(&self) -> &Cart { &self.cart }
+    fn cart_mut(&mut self) -> &mut Cart { &mut self.cart }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            self.cart.cpu_write(address, data);
+            return;
+        }
+
+        let reg = ((address as usize) - 0x8000) / 0x2000;
+
+        if (data & 0x80) == 0x80 {
+            match reg {
+                0 => {
+                    self.reg_bit[0] = 0;
+                    self.reg_val[0] = 0;
+                    self.prg_switch_area = 1;
+                    self.prg_switch_size = 1;
+                    self.vrom_switch_size = 0;
+                },
+                3 => {
+                    self.reg_bit = [0; 4];
+                    self.reg_val = [0; 4];
+                },
+                _ => {
+                    self.reg_bit[reg] = 0;
+                    self.reg_val[reg] = 0;
+                }
+            }
+            return;
+        }
+
+        self.reg_val[reg] += ((data & 0x1) as usize) << self.reg_bit[reg];
+        self.reg_bit[reg] += 1;
+
+        if self.reg_bit[reg] == 5 {
+            let val = self.reg_val[reg];
+            self.reg_bit[reg] = 0;
+            self.reg_val[reg] = 0;
+
+            match reg {
+                0 => self.control_write(val),
+                1 => self.chr_0_write(val),
+                2 => self.chr_1_write(val),
+                _ => self.prg_write(val)
+            }
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        for reg in 0..4 {
+            state.push(self.reg_bit[reg] as u8);
+            state.push(self.reg_val[reg] as u8);
+        }
+        state.push(self.prg_switch_area);
+        state.push(self.prg_switch_size);
+        state.push(self.vrom_switch_size);
+
+        self.cart.save_state(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut state = state;
+        for reg in 0..4 {
+            self.reg_bit[reg] = try!(state.read_u8()) as usize;
+            self.reg_val[reg] = try!(state.read_u8()) as usize;
+        }
+        self.prg_switch_area = try!(state.read_u8());
+        self.prg_switch_size = try!(state.read_u8());
+        self.vrom_switch_size = try!(state.read_u8());
+
+        self.cart.load_state(&mut state)
+    }
+}
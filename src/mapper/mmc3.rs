@@ -0,0 +1,152 @@
+use std::io;
+
+use util::BitReader;
+use mapper::{Mapper, Cart};
+use ppu::mirroring;
+
+// Eight bank registers, written through a select port at $8000 and a data
+// port at $8001, plus a scanline counter that raises IRQs.  Registers are
+// decoded on A0 and the top three address lines, so they repeat through
+// each 8k of ROM.
+pub struct Mmc3 {
+    cart: Cart,
+
+    bank_select: u8,
+    banks: [u8; 8],
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool
+}
+
+pub fn new(cart: Cart) -> Box<dyn Mapper> {
+    let mut mmc3 = Mmc3 {
+        cart: cart,
+        bank_select: 0,
+        banks: [0, 2, 4, 5, 6, 7, 0, 1],
+        irq_latch: 0,
+        irq_counter: 0,
+        irq_reload: false,
+        irq_enabled: false,
+        irq_pending: false
+    };
+    mmc3.update_banks();
+
+    Box::new(mmc3)
+}
+
+impl Mmc3 {
+    fn update_banks(&mut self) {
+        // The last two 8k banks, in 4k pages
+        let second_last = self.cart.prg_rom.len() - 4;
+        let last = self.cart.prg_rom.len() - 2;
+
+        if (self.bank_select & 0x40) == 0 {
+            self.cart.switch_8k_prg_page(self.banks[6] as usize * 2, 0);
+            self.cart.switch_8k_prg_page(second_last, 2);
+        }
+        else {
+            self.cart.switch_8k_prg_page(second_last, 0);
+            self.cart.switch_8k_prg_page(self.banks[6] as usize * 2, 2);
+        }
+        self.cart.switch_8k_prg_page(self.banks[7] as usize * 2, 1);
+        self.cart.switch_8k_prg_page(last, 3);
+
+        // Two 2k banks and four 1k banks, with the halves swapped by bit 7
+        let (two_k_area, one_k_area) = if (self.bank_select & 0x80) == 0 { (0, 4) } else { (2, 0) };
+        self.cart.switch_2k_chr_page((self.banks[0] & 0xfe) as usize, two_k_area);
+        self.cart.switch_2k_chr_page((self.banks[1] & 0xfe) as usize, two_k_area + 1);
+        for i in 0..4 {
+            self.cart.switch_1k_chr_page(self.banks[2 + i] as usize, one_k_area + i);
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cart(&self) -> &Cart { &self.cart }
+    fn cart_mut(&mut self) -> &mut Cart { &mut self.cart }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            self.cart.cpu_write(address, data);
+            return;
+        }
+
+        match address & 0xe001 {
+            0x8000 => {
+                self.bank_select = data;
+                self.update_banks();
+            },
+            0x8001 => {
+                self.banks[(self.bank_select & 0x7) as usize] = data;
+                self.update_banks();
+            },
+            0xa000 => {
+                if self.cart.mirroring != mirroring::FOUR_SCREEN {
+                    self.cart.mirroring = if (data & 1) == 1 { mirroring::HORIZONTAL } else { mirroring::VERTICAL };
+                }
+            },
+            0xa001 => {
+                //currently we ignore this
+            },
+            0xc000 => self.irq_latch = data,
+            0xc001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            0xe000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            _ => self.irq_enabled = true
+        }
+    }
+
+    fn clock_scanline(&mut self) {
+        if (self.irq_counter == 0) || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        }
+        else {
+            self.irq_counter -= 1;
+        }
+
+        if (self.irq_counter == 0) && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.bank_select];
+        state.extend(self.banks.iter().cloned());
+        state.push(self.irq_latch);
+        state.push(self.irq_counter);
+        state.push(self.irq_reload as u8);
+        state.push(self.irq_enabled as u8);
+        state.push(self.irq_pending as u8);
+
+        self.cart.save_state(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut state = state;
+        self.bank_select = try!(state.read_u8());
+        for i in 0..8 {
+            self.banks[i] = try!(state.read_u8());
+        }
+        self.irq_latch = try!(state.read_u8());
+        self.irq_counter = try!(state.read_u8());
+        self.irq_reload = try!(state.read_u8()) != 0;
+        self.irq_enabled = try!(state.read_u8()) != 0;
+        self.irq_pending = try!(state.read_u8()) != 0;
+
+        self.cart.load_state(&mut state)
+    }
+}
@@ -0,0 +1,296 @@
+use std::io;
+use std::io::prelude::*;
+use std::cmp;
+
+use util::BitReader;
+use ppu::name_table_offset;
+use cart::CartHeader;
+
+mod nrom;
+mod mmc1;
+mod uxrom;
+mod cnrom;
+mod mmc3;
+
+type Constructor = fn(Cart) -> Box<dyn Mapper>;
+
+// Every supported board, by iNES mapper number.  A new board is a module
+// with a `new` that builds it around a Cart, plus a line here.
+static BOARDS : &[(u16, &str, Constructor)] = &[
+    (0, "NROM", nrom::new),
+    (1, "MMC1", mmc1::new),
+    (2, "UxROM", uxrom::new),
+    (3, "CNROM", cnrom::new),
+    (4, "MMC3", mmc3::new),
+];
+
+pub fn create(number: u16, cart: Cart) -> Option<Box<dyn Mapper>> {
+    BOARDS.iter()
+        .find(|&&(n, _, _)| n == number)
+        .map(|&(_, _, new)| new(cart))
+}
+
+pub fn name(number: u16) -> Option<&'static str> {
+    BOARDS.iter()
+        .find(|&&(n, _, _)| n == number)
+        .map(|&(_, name, _)| name)
+}
+
+// UNIF names boards rather than numbering them.  These are the boards
+// built on the mappers above, without the "NES-", "HVC-" etc. prefix.
+static UNIF_BOARDS : &[(&str, u16)] = &[
+    ("NROM", 0), ("NROM-128", 0), ("NROM-256", 0), ("RROM", 0), ("RROM-128", 0),
+    ("SAROM", 1), ("SBROM", 1), ("SCROM", 1), ("SEROM", 1), ("SFROM", 1), ("SGROM", 1),
+    ("SHROM", 1), ("SJROM", 1), ("SKROM", 1), ("SLROM", 1), ("SL1ROM", 1), ("SNROM", 1),
+    ("SOROM", 1), ("SUROM", 1), ("SXROM", 1),
+    ("UNROM", 2), ("UOROM", 2),
+    ("CNROM", 3),
+    ("TBROM", 4), ("TEROM", 4), ("TFROM", 4), ("TGROM", 4), ("TKROM", 4), ("TLROM", 4),
+    ("TL1ROM", 4), ("TR1ROM", 4), ("TSROM", 4), ("TVROM", 4),
+];
+
+// Eg: "NES-TLROM" and "TLROM" are both mapper 4
+pub fn unif_board_number(board: &str) -> Option<u16> {
+    let board = board.trim().to_uppercase();
+    let name = match board.find('-') {
+        Some(i) if ["NES", "HVC", "UNL", "BTL", "BMC", "KONAMI"].contains(&&board[..i]) => &board[i + 1..],
+        _ => &board[..]
+    };
+
+    UNIF_BOARDS.iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, number)| number)
+}
+
+// What the console sees with no cartridge inserted
+pub fn none() -> Box<dyn Mapper> {
+    nrom::new(Cart::new(CartHeader::new(), &[], &[]))
+}
+
+// The memory on a cartridge and the windows the CPU and PPU see it through.
+// PRG is kept in 4k pages and CHR in 1k pages, so boards can switch banks
+// of any size by pointing several windows at once.
+pub struct Cart {
+    pub header: CartHeader,
+
+    pub prg_rom: Vec<Vec<u8>>,
+    pub chr_rom: Vec<Vec<u8>>,
+    pub prg_ram: Vec<u8>,
+    pub is_vram: bool,
+    pub mirroring: u8,
+
+    // In 16k and 8k units, as the iNES header counts them
+    pub num_prg_pages: usize,
+    pub num_chr_pages: usize,
+
+    pub active_prg_page: Vec<usize>,
+    pub active_chr_page: Vec<usize>
+}
+
+impl Cart {
+    // PRG is padded out to a whole 16k.  Carts without CHR ROM get at
+    // least 8k of CHR RAM.
+    pub fn new(header: CartHeader, prg: &[u8], chr: &[u8]) -> Cart {
+        let mut prg = prg.to_vec();
+        let prg_size = cmp::max((prg.len() + 0x3fff) & !0x3fff, 0x4000);
+        prg.resize(prg_size, 0);
+        let prg_rom : Vec<Vec<u8>> = prg.chunks(0x1000).map(|page| page.to_vec()).collect();
+
+        let is_vram = chr.is_empty();
+        let chr_rom : Vec<Vec<u8>> =
+            if is_vram {
+                let chr_ram_size = cmp::max(header.chr_ram_size + header.chr_nvram_size, 0x2000);
+                vec![vec![0; 0x400]; chr_ram_size / 0x400]
+            }
+            else {
+                let mut chr = chr.to_vec();
+                let chr_size = (chr.len() + 0x1fff) & !0x1fff;
+                chr.resize(chr_size, 0);
+                chr.chunks(0x400).map(|page| page.to_vec()).collect()
+            };
+
+        // $6000-$7FFF is always backed, however little RAM the cart has
+        let prg_ram_size = cmp::max(header.prg_ram_size + header.prg_nvram_size, 0x2000);
+
+        let mut cart = Cart {
+            num_prg_pages: prg_rom.len() / 4,
+            num_chr_pages: if is_vram { 0 } else { chr_rom.len() / 8 },
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+            prg_ram: vec![0; prg_ram_size],
+            is_vram: is_vram,
+            mirroring: header.mirroring,
+            header: header,
+            active_prg_page: (0..8).collect(),
+            active_chr_page: (0..8).collect()
+        };
+
+        // A 16k cart appears at both $8000 and $C000
+        if cart.num_prg_pages == 1 {
+            cart.switch_16k_prg_page(0, 1);
+        }
+
+        cart
+    }
+
+    pub fn cpu_peek(&self, address: u16) -> u8 {
+        match address {
+            0x6000...0x7FFF => self.prg_ram[(address as usize) - 0x6000],
+            0x8000...0xFFFF => {
+                let offset = (address as usize) - 0x8000;
+                self.prg_rom[self.active_prg_page[offset / 0x1000]][offset % 0x1000]
+            },
+            _ => 0
+        }
+    }
+
+    pub fn cpu_write(&mut self, address: u16, data: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.prg_ram[(address as usize) - 0x6000] = data;
+        }
+    }
+
+    pub fn ppu_read(&self, address: u16) -> u8 {
+        let address = (address as usize) & 0x1fff;
+        self.chr_rom[self.active_chr_page[address / 0x400]][address % 0x400]
+    }
+
+    pub fn ppu_write(&mut self, address: u16, data: u8) {
+        if self.is_vram {
+            let address = (address as usize) & 0x1fff;
+            self.chr_rom[self.active_chr_page[address / 0x400]][address % 0x400] = data;
+        }
+    }
+
+    pub fn switch_32k_prg_page(&mut self, start: usize) {
+        for i in 0..8 {
+            self.active_prg_page[i] = (start + i) % self.prg_rom.len();
+        }
+    }
+
+    pub fn switch_16k_prg_page(&mut self, start: usize, area: usize) {
+        for i in 0..4 {
+            self.active_prg_page[4 * area + i] = (start + i) % self.prg_rom.len();
+        }
+    }
+
+    pub fn switch_8k_prg_page(&mut self, start: usize, area: usize) {
+        for i in 0..2 {
+            self.active_prg_page[2 * area + i] = (start + i) % self.prg_rom.len();
+        }
+    }
+
+    pub fn switch_8k_chr_page(&mut self, start: usize) {
+        for i in 0..8 {
+            self.active_chr_page[i] = (start + i) % self.chr_rom.len();
+        }
+    }
+
+    pub fn switch_4k_chr_page(&mut self, start: usize, area: usize) {
+        for i in 0..4 {
+            self.active_chr_page[4 * area + i] = (start + i) % self.chr_rom.len();
+        }
+    }
+
+    pub fn switch_2k_chr_page(&mut self, start: usize, area: usize) {
+        for i in 0..2 {
+            self.active_chr_page[2 * area + i] = (start + i) % self.chr_rom.len();
+        }
+    }
+
+    pub fn switch_1k_chr_page(&mut self, start: usize, area: usize) {
+        self.active_chr_page[area] = start % self.chr_rom.len();
+    }
+
+    // The bank windows, mirroring and RAM.  ROM is left out, as it comes
+    // back with the cart.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        for &page in self.active_prg_page.iter().chain(self.active_chr_page.iter()) {
+            state.push((page >> 8) as u8);
+            state.push(page as u8);
+        }
+        state.push(self.mirroring);
+        state.extend(self.prg_ram.iter().cloned());
+
+        if self.is_vram {
+            for page in &self.chr_rom {
+                state.extend(page.iter().cloned());
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut &[u8]) -> Result<(), io::Error> {
+        for i in 0..8 {
+            self.active_prg_page[i] = (try!(state.read_u16_be()) as usize) % self.prg_rom.len();
+        }
+        for i in 0..8 {
+            self.active_chr_page[i] = (try!(state.read_u16_be()) as usize) % self.chr_rom.len();
+        }
+        self.mirroring = try!(state.read_u8());
+        try!(state.read_exact(&mut self.prg_ram));
+
+        if self.is_vram {
+            for page in &mut self.chr_rom {
+                try!(state.read_exact(page));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The board inside a cartridge.  It sees every CPU access to $4020-$FFFF
+// and every PPU access to pattern memory, decides where the nametables
+// live and can pull the CPU's IRQ line.  Boards only need to override the
+// hooks they use, the rest go straight to the Cart.
+pub trait Mapper {
+    fn cart(&self) -> &Cart;
+    fn cart_mut(&mut self) -> &mut Cart;
+
+    // Reads without side effects, for the debugger and the tracer
+    fn cpu_peek(&self, address: u16) -> u8 {
+        self.cart().cpu_peek(address)
+    }
+
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        self.cpu_peek(address)
+    }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        self.cart_mut().cpu_write(address, data);
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.cart().ppu_read(address)
+    }
+
+    fn ppu_write(&mut self, address: u16, data: u8) {
+        self.cart_mut().ppu_write(address, data);
+    }
+
+    // Where a $2000-$2FFF access lands in the console's nametable RAM
+    fn name_table_offset(&self, address: usize) -> usize {
+        name_table_offset(self.cart().mirroring, address)
+    }
+
+    // Called when PPU A12 rises after being low for a while, which is once
+    // per rendered line with the usual pattern table layout.  The fast
+    // renderer calls it once per line regardless.
+    fn clock_scanline(&mut self) {}
+
+    // Level of the board's IRQ output
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        self.cart().save_state(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), io::Error> {
+        let mut state = state;
+        self.cart_mut().load_state(&mut state)
+    }
+}
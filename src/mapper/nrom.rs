@@ -0,0 +1,15 @@
+use mapper::{Mapper, Cart};
+
+// No bank switching at all
+pub struct Nrom {
+    cart: Cart
+}
+
+pub fn new(cart: Cart) -> Box<dyn Mapper> {
+    Box::new(Nrom { cart: cart })
+}
+
+impl Mapper for Nrom {
+    fn cart(&self) -> &Cart { &self.cart }
+    fn cart_mut(&mut self) -> &mut Cart { &mut self.cart }
+}
@@ -0,0 +1,27 @@
+use mapper::{Mapper, Cart};
+
+// Any write to ROM picks the 16k bank at $8000.  The last bank stays
+// fixed at $C000.
+pub struct Uxrom {
+    cart: Cart
+}
+
+pub fn new(cart: Cart) -> Box<dyn Mapper> {
+    let mut cart = cart;
+    let num_prg = cart.num_prg_pages;
+    cart.switch_16k_prg_page((num_prg - 1) * 4, 1);
+
+    Box::new(Uxrom { cart: cart })
+}
+
+impl Mapper for Uxrom {
+    fn cart(&self) -> &Cart { &self.cart }
+    fn cart_mut(&mut self) -> &mut Cart { &mut self.cart }
+
+    fn cpu_write(&mut self, address: u16, data: u8) {
+        match address {
+            0x8000...0xFFFF => self.cart.switch_16k_prg_page(data as usize * 4, 0),
+            _ => self.cart.cpu_write(address, data)
+        }
+    }
+}
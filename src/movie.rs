@@ -0,0 +1,83 @@
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+
+use joypad::button;
+use nes::Nes;
+
+// One frame of recorded input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovieFrame {
+    pub reset: bool,
+    pub buttons: [u8; 2]
+}
+
+// Input recorded in FCEUX's .fm2 format, played back a frame at a time
+pub struct Movie {
+    pub frames: Vec<MovieFrame>
+}
+
+// The order buttons are written in on an .fm2 input line
+const FM2_BUTTONS : [u8; 8] = [
+    button::RIGHT, button::LEFT, button::DOWN, button::UP,
+    button::START, button::SELECT, button::B, button::A
+];
+
+fn fm2_buttons(field: &str) -> u8 {
+    field.chars().zip(FM2_BUTTONS.iter())
+        .filter(|&(c, _)| c != '.' && c != ' ')
+        .fold(0, |buttons, (_, &b)| buttons | b)
+}
+
+impl Movie {
+    pub fn load(fname: &str) -> Result<Movie, io::Error> {
+        let mut text = String::new();
+        try!(try!(File::open(fname)).read_to_string(&mut text));
+
+        Movie::parse_fm2(&text)
+    }
+
+    // Header lines are "key value" and are skipped.  Input lines look like
+    // "|0|RLDUTSBA|........||", the commands first then each port.
+    pub fn parse_fm2(text: &str) -> Result<Movie, io::Error> {
+        let mut frames = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            if !line.starts_with('|') {
+                continue;
+            }
+
+            let fields : Vec<&str> = line.split('|').collect();
+            let commands = match fields.get(1).map(|c| c.trim().parse::<u8>()) {
+                Some(Ok(commands)) => commands,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Bad movie input on line {}", number + 1)))
+            };
+
+            let port = |i: usize| fields.get(i).map_or(0, |f| fm2_buttons(f));
+            frames.push(MovieFrame {
+                // Soft and hard resets are both played back as a reset
+                reset: (commands & 0x3) != 0,
+                buttons: [port(2), port(3)]
+            });
+        }
+
+        Ok(Movie { frames: frames })
+    }
+
+    // Sets the input for the frame the console is about to run.  Returns
+    // false once the movie has run out.
+    pub fn play_frame(&self, nes: &mut Nes) -> bool {
+        match self.frames.get(nes.frame_count) {
+            Some(frame) => {
+                if frame.reset {
+                    nes.reset();
+                }
+                nes.set_buttons(0, frame.buttons[0]);
+                nes.set_buttons(1, frame.buttons[1]);
+                true
+            },
+            None => false
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use mmu::Mmu;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative
+}
+
+impl Mode {
+    // Size of the instruction, including the opcode byte
+    pub fn length(&self) -> u16 {
+        match *self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Immediate | Mode::ZeroPage | Mode::ZeroPageX | Mode::ZeroPageY |
+            Mode::IndirectX | Mode::IndirectY | Mode::Relative => 2,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Instr {
+    Adc, And, Asl, Bcc, Bcs, Beq, Bit, Bmi, Bne, Bpl, Brk, Bvc, Bvs, Clc,
+    Cld, Cli, Clv, Cmp, Cpx, Cpy, Dec, Dex, Dey, Eor, Inc, Inx, Iny, Jmp,
+    Jsr, Lda, Ldx, Ldy, Lsr, Nop, Ora, Pha, Php, Pla, Plp, Rol, Ror, Rti,
+    Rts, Sbc, Sec, Sed, Sei, Sta, Stx, Sty, Tax, Tay, Tsx, Txa, Txs, Tya,
+    
+    // Unofficial instructions
+    Ahx, Alr, Anc, Arr, Axs, Dcp, Isc, Kil, Las, Lax, Lxa, Rla, Rra, Sax,
+    Shx, Shy, Slo, Sre, Tas, Xaa
+}
+
+pub struct Opcode {
+    pub instr: Instr,
+    pub name: &'static str,
+    pub mode: Mode,
+    pub cycles: u8,
+    // Whether crossing a page while indexing costs an extra cycle
+    pub page_penalty: bool,
+    pub unofficial: bool
+}
+
+const fn op(instr: Instr, name: &'static str, mode: Mode, cycles: u8, page_penalty: bool) -> Opcode {
+    Opcode { instr: instr, name: name, mode: mode, cycles: cycles, page_penalty: page_penalty, unofficial: false }
+}
+
+const fn ill(instr: Instr, name: &'static str, mode: Mode, cycles: u8, page_penalty: bool) -> Opcode {
+    Opcode { instr: instr, name: name, mode: mode, cycles: cycles, page_penalty: page_penalty, unofficial: true }
+}
+
+use self::Instr::*;
+use self::Mode::*;
+
+pub static OPCODES : [Opcode; 256] = [
+    op(Brk, "brk", Implied, 7, false), // 0x00
+    op(Ora, "ora", IndirectX, 6, false), // 0x01
+    ill(Kil, "kil", Implied, 2, false), // 0x02
+    ill(Slo, "slo", IndirectX, 8, false), // 0x03
+    ill(Nop, "nop", ZeroPage, 3, false), // 0x04
+    op(Ora, "ora", ZeroPage, 3, false), // 0x05
+    op(Asl, "asl", ZeroPage, 5, false), // 0x06
+    ill(Slo, "slo", ZeroPage, 5, false), // 0x07
+    op(Php, "php", Implied, 3, false), // 0x08
+    op(Ora, "ora", Immediate, 2, false), // 0x09
+    op(Asl, "asl", Accumulator, 2, false), // 0x0a
+    ill(Anc, "anc", Immediate, 2, false), // 0x0b
+    ill(Nop, "nop", Absolute, 4, false), // 0x0c
+    op(Ora, "ora", Absolute, 4, false), // 0x0d
+    op(Asl, "asl", Absolute, 6, false), // 0x0e
+    ill(Slo, "slo", Absolute, 6, false), // 0x0f
+    op(Bpl, "bpl", Relative, 2, false), // 0x10
+    op(Ora, "ora", IndirectY, 5, true), // 0x11
+    ill(Kil, "kil", Implied, 2, false), // 0x12
+    ill(Slo, "slo", IndirectY, 8, false), // 0x13
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0x14
+    op(Ora, "ora", ZeroPageX, 4, false), // 0x15
+    op(Asl, "asl", ZeroPageX, 6, false), // 0x16
+    ill(Slo, "slo", ZeroPageX, 6, false), // 0x17
+    op(Clc, "clc", Implied, 2, false), // 0x18
+    op(Ora, "ora", AbsoluteY, 4, true), // 0x19
+    ill(Nop, "nop", Implied, 2, false), // 0x1a
+    ill(Slo, "slo", AbsoluteY, 7, false), // 0x1b
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0x1c
+    op(Ora, "ora", AbsoluteX, 4, true), // 0x1d
+    op(Asl, "asl", AbsoluteX, 7, false), // 0x1e
+    ill(Slo, "slo", AbsoluteX, 7, false), // 0x1f
+    op(Jsr, "jsr", Absolute, 6, false), // 0x20
+    op(And, "and", IndirectX, 6, false), // 0x21
+    ill(Kil, "kil", Implied, 2, false), // 0x22
+    ill(Rla, "rla", IndirectX, 8, false), // 0x23
+    op(Bit, "bit", ZeroPage, 3, false), // 0x24
+    op(And, "and", ZeroPage, 3, false), // 0x25
+    op(Rol, "rol", ZeroPage, 5, false), // 0x26
+    ill(Rla, "rla", ZeroPage, 5, false), // 0x27
+    op(Plp, "plp", Implied, 4, false), // 0x28
+    op(And, "and", Immediate, 2, false), // 0x29
+    op(Rol, "rol", Accumulator, 2, false), // 0x2a
+    ill(Anc, "anc", Immediate, 2, false), // 0x2b
+    op(Bit, "bit", Absolute, 4, false), // 0x2c
+    op(And, "and", Absolute, 4, false), // 0x2d
+    op(Rol, "rol", Absolute, 6, false), // 0x2e
+    ill(Rla, "rla", Absolute, 6, false), // 0x2f
+    op(Bmi, "bmi", Relative, 2, false), // 0x30
+    op(And, "and", IndirectY, 5, true), // 0x31
+    ill(Kil, "kil", Implied, 2, false), // 0x32
+    ill(Rla, "rla", IndirectY, 8, false), // 0x33
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0x34
+    op(And, "and", ZeroPageX, 4, false), // 0x35
+    op(Rol, "rol", ZeroPageX, 6, false), // 0x36
+    ill(Rla, "rla", ZeroPageX, 6, false), // 0x37
+    op(Sec, "sec", Implied, 2, false), // 0x38
+    op(And, "and", AbsoluteY, 4, true), // 0x39
+    ill(Nop, "nop", Implied, 2, false), // 0x3a
+    ill(Rla, "rla", AbsoluteY, 7, false), // 0x3b
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0x3c
+    op(And, "and", AbsoluteX, 4, true), // 0x3d
+    op(Rol, "rol", AbsoluteX, 7, false), // 0x3e
+    ill(Rla, "rla", AbsoluteX, 7, false), // 0x3f
+    op(Rti, "rti", Implied, 6, false), // 0x40
+    op(Eor, "eor", IndirectX, 6, false), // 0x41
+    ill(Kil, "kil", Implied, 2, false), // 0x42
+    ill(Sre, "sre", IndirectX, 8, false), // 0x43
+    ill(Nop, "nop", ZeroPage, 3, false), // 0x44
+    op(Eor, "eor", ZeroPage, 3, false), // 0x45
+    op(Lsr, "lsr", ZeroPage, 5, false), // 0x46
+    ill(Sre, "sre", ZeroPage, 5, false), // 0x47
+    op(Pha, "pha", Implied, 3, false), // 0x48
+    op(Eor, "eor", Immediate, 2, false), // 0x49
+    op(Lsr, "lsr", Accumulator, 2, false), // 0x4a
+    ill(Alr, "alr", Immediate, 2, false), // 0x4b
+    op(Jmp, "jmp", Absolute, 3, false), // 0x4c
+    op(Eor, "eor", Absolute, 4, false), // 0x4d
+    op(Lsr, "lsr", Absolute, 6, false), // 0x4e
+    ill(Sre, "sre", Absolute, 6, false), // 0x4f
+    op(Bvc, "bvc", Relative, 2, false), // 0x50
+    op(Eor, "eor", IndirectY, 5, true), // 0x51
+    ill(Kil, "kil", Implied, 2, false), // 0x52
+    ill(Sre, "sre", IndirectY, 8, false), // 0x53
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0x54
+    op(Eor, "eor", ZeroPageX, 4, false), // 0x55
+    op(Lsr, "lsr", ZeroPageX, 6, false), // 0x56
+    ill(Sre, "sre", ZeroPageX, 6, false), // 0x57
+    op(Cli, "cli", Implied, 2, false), // 0x58
+    op(Eor, "eor", AbsoluteY, 4, true), // 0x59
+    ill(Nop, "nop", Implied, 2, false), // 0x5a
+    ill(Sre, "sre", AbsoluteY, 7, false), // 0x5b
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0x5c
+    op(Eor, "eor", AbsoluteX, 4, true), // 0x5d
+    op(Lsr, "lsr", AbsoluteX, 7, false), // 0x5e
+    ill(Sre, "sre", AbsoluteX, 7, false), // 0x5f
+    op(Rts, "rts", Implied, 6, false), // 0x60
+    op(Adc, "adc", IndirectX, 6, false), // 0x61
+    ill(Kil, "kil", Implied, 2, false), // 0x62
+    ill(Rra, "rra", IndirectX, 8, false), // 0x63
+    ill(Nop, "nop", ZeroPage, 3, false), // 0x64
+    op(Adc, "adc", ZeroPage, 3, false), // 0x65
+    op(Ror, "ror", ZeroPage, 5, false), // 0x66
+    ill(Rra, "rra", ZeroPage, 5, false), // 0x67
+    op(Pla, "pla", Implied, 4, false), // 0x68
+    op(Adc, "adc", Immediate, 2, false), // 0x69
+    op(Ror, "ror", Accumulator, 2, false), // 0x6a
+    ill(Arr, "arr", Immediate, 2, false), // 0x6b
+    op(Jmp, "jmp", Indirect, 5, false), // 0x6c
+    op(Adc, "adc", Absolute, 4, false), // 0x6d
+    op(Ror, "ror", Absolute, 6, false), // 0x6e
+    ill(Rra, "rra", Absolute, 6, false), // 0x6f
+    op(Bvs, "bvs", Relative, 2, false), // 0x70
+    op(Adc, "adc", IndirectY, 5, true), // 0x71
+    ill(Kil, "kil", Implied, 2, false), // 0x72
+    ill(Rra, "rra", IndirectY, 8, false), // 0x73
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0x74
+    op(Adc, "adc", ZeroPageX, 4, false), // 0x75
+    op(Ror, "ror", ZeroPageX, 6, false), // 0x76
+    ill(Rra, "rra", ZeroPageX, 6, false), // 0x77
+    op(Sei, "sei", Implied, 2, false), // 0x78
+    op(Adc, "adc", AbsoluteY, 4, true), // 0x79
+    ill(Nop, "nop", Implied, 2, false), // 0x7a
+    ill(Rra, "rra", AbsoluteY, 7, false), // 0x7b
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0x7c
+    op(Adc, "adc", AbsoluteX, 4, true), // 0x7d
+    op(Ror, "ror", AbsoluteX, 7, false), // 0x7e
+    ill(Rra, "rra", AbsoluteX, 7, false), // 0x7f
+    ill(Nop, "nop", Immediate, 2, false), // 0x80
+    op(Sta, "sta", IndirectX, 6, false), // 0x81
+    ill(Nop, "nop", Immediate, 2, false), // 0x82
+    ill(Sax, "sax", IndirectX, 6, false), // 0x83
+    op(Sty, "sty", ZeroPage, 3, false), // 0x84
+    op(Sta, "sta", ZeroPage, 3, false), // 0x85
+    op(Stx, "stx", ZeroPage, 3, false), // 0x86
+    ill(Sax, "sax", ZeroPage, 3, false), // 0x87
+    op(Dey, "dey", Implied, 2, false), // 0x88
+    ill(Nop, "nop", Immediate, 2, false), // 0x89
+    op(Txa, "txa", Implied, 2, false), // 0x8a
+    ill(Xaa, "xaa", Immediate, 2, false), // 0x8b
+    op(Sty, "sty", Absolute, 4, false), // 0x8c
+    op(Sta, "sta", Absolute, 4, false), // 0x8d
+    op(Stx, "stx", Absolute, 4, false), // 0x8e
+    ill(Sax, "sax", Absolute, 4, false), // 0x8f
+    op(Bcc, "bcc", Relative, 2, false), // 0x90
+    op(Sta, "sta", IndirectY, 6, false), // 0x91
+    ill(Kil, "kil", Implied, 2, false), // 0x92
+    ill(Ahx, "ahx", IndirectY, 6, false), // 0x93
+    op(Sty, "sty", ZeroPageX, 4, false), // 0x94
+    op(Sta, "sta", ZeroPageX, 4, false), // 0x95
+    op(Stx, "stx", ZeroPageY, 4, false), // 0x96
+    ill(Sax, "sax", ZeroPageY, 4, false), // 0x97
+    op(Tya, "tya", Implied, 2, false), // 0x98
+    op(Sta, "sta", AbsoluteY, 5, false), // 0x99
+    op(Txs, "txs", Implied, 2, false), // 0x9a
+    ill(Tas, "tas", AbsoluteY, 5, false), // 0x9b
+    ill(Shy, "shy", AbsoluteX, 5, false), // 0x9c
+    op(Sta, "sta", AbsoluteX, 5, false), // 0x9d
+    ill(Shx, "shx", AbsoluteY, 5, false), // 0x9e
+    ill(Ahx, "ahx", AbsoluteY, 5, false), // 0x9f
+    op(Ldy, "ldy", Immediate, 2, false), // 0xa0
+    op(Lda, "lda", IndirectX, 6, false), // 0xa1
+    op(Ldx, "ldx", Immediate, 2, false), // 0xa2
+    ill(Lax, "lax", IndirectX, 6, false), // 0xa3
+    op(Ldy, "ldy", ZeroPage, 3, false), // 0xa4
+    op(Lda, "lda", ZeroPage, 3, false), // 0xa5
+    op(Ldx, "ldx", ZeroPage, 3, false), // 0xa6
+    ill(Lax, "lax", ZeroPage, 3, false), // 0xa7
+    op(Tay, "tay", Implied, 2, false), // 0xa8
+    op(Lda, "lda", Immediate, 2, false), // 0xa9
+    op(Tax, "tax", Implied, 2, false), // 0xaa
+    ill(Lxa, "lxa", Immediate, 2, false), // 0xab
+    op(Ldy, "ldy", Absolute, 4, false), // 0xac
+    op(Lda, "lda", Absolute, 4, false), // 0xad
+    op(Ldx, "ldx", Absolute, 4, false), // 0xae
+    ill(Lax, "lax", Absolute, 4, false), // 0xaf
+    op(Bcs, "bcs", Relative, 2, false), // 0xb0
+    op(Lda, "lda", IndirectY, 5, true), // 0xb1
+    ill(Kil, "kil", Implied, 2, false), // 0xb2
+    ill(Lax, "lax", IndirectY, 5, true), // 0xb3
+    op(Ldy, "ldy", ZeroPageX, 4, false), // 0xb4
+    op(Lda, "lda", ZeroPageX, 4, false), // 0xb5
+    op(Ldx, "ldx", ZeroPageY, 4, false), // 0xb6
+    ill(Lax, "lax", ZeroPageY, 4, false), // 0xb7
+    op(Clv, "clv", Implied, 2, false), // 0xb8
+    op(Lda, "lda", AbsoluteY, 4, true), // 0xb9
+    op(Tsx, "tsx", Implied, 2, false), // 0xba
+    ill(Las, "las", AbsoluteY, 4, true), // 0xbb
+    op(Ldy, "ldy", AbsoluteX, 4, true), // 0xbc
+    op(Lda, "lda", AbsoluteX, 4, true), // 0xbd
+    op(Ldx, "ldx", AbsoluteY, 4, true), // 0xbe
+    ill(Lax, "lax", AbsoluteY, 4, true), // 0xbf
+    op(Cpy, "cpy", Immediate, 2, false), // 0xc0
+    op(Cmp, "cmp", IndirectX, 6, false), // 0xc1
+    ill(Nop, "nop", Immediate, 2, false), // 0xc2
+    ill(Dcp, "dcp", IndirectX, 8, false), // 0xc3
+    op(Cpy, "cpy", ZeroPage, 3, false), // 0xc4
+    op(Cmp, "cmp", ZeroPage, 3, false), // 0xc5
+    op(Dec, "dec", ZeroPage, 5, false), // 0xc6
+    ill(Dcp, "dcp", ZeroPage, 5, false), // 0xc7
+    op(Iny, "iny", Implied, 2, false), // 0xc8
+    op(Cmp, "cmp", Immediate, 2, false), // 0xc9
+    op(Dex, "dex", Implied, 2, false), // 0xca
+    ill(Axs, "axs", Immediate, 2, false), // 0xcb
+    op(Cpy, "cpy", Absolute, 4, false), // 0xcc
+    op(Cmp, "cmp", Absolute, 4, false), // 0xcd
+    op(Dec, "dec", Absolute, 6, false), // 0xce
+    ill(Dcp, "dcp", Absolute, 6, false), // 0xcf
+    op(Bne, "bne", Relative, 2, false), // 0xd0
+    op(Cmp, "cmp", IndirectY, 5, true), // 0xd1
+    ill(Kil, "kil", Implied, 2, false), // 0xd2
+    ill(Dcp, "dcp", IndirectY, 8, false), // 0xd3
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0xd4
+    op(Cmp, "cmp", ZeroPageX, 4, false), // 0xd5
+    op(Dec, "dec", ZeroPageX, 6, false), // 0xd6
+    ill(Dcp, "dcp", ZeroPageX, 6, false), // 0xd7
+    op(Cld, "cld", Implied, 2, false), // 0xd8
+    op(Cmp, "cmp", AbsoluteY, 4, true), // 0xd9
+    ill(Nop, "nop", Implied, 2, false), // 0xda
+    ill(Dcp, "dcp", AbsoluteY, 7, false), // 0xdb
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0xdc
+    op(Cmp, "cmp", AbsoluteX, 4, true), // 0xdd
+    op(Dec, "dec", AbsoluteX, 7, false), // 0xde
+    ill(Dcp, "dcp", AbsoluteX, 7, false), // 0xdf
+    op(Cpx, "cpx", Immediate, 2, false), // 0xe0
+    op(Sbc, "sbc", IndirectX, 6, false), // 0xe1
+    ill(Nop, "nop", Immediate, 2, false), // 0xe2
+    ill(Isc, "isc", IndirectX, 8, false), // 0xe3
+    op(Cpx, "cpx", ZeroPage, 3, false), // 0xe4
+    op(Sbc, "sbc", ZeroPage, 3, false), // 0xe5
+    op(Inc, "inc", ZeroPage, 5, false), // 0xe6
+    ill(Isc, "isc", ZeroPage, 5, false), // 0xe7
+    op(Inx, "inx", Implied, 2, false), // 0xe8
+    op(Sbc, "sbc", Immediate, 2, false), // 0xe9
+    op(Nop, "nop", Implied, 2, false), // 0xea
+    ill(Sbc, "sbc", Immediate, 2, false), // 0xeb
+    op(Cpx, "cpx", Absolute, 4, false), // 0xec
+    op(Sbc, "sbc", Absolute, 4, false), // 0xed
+    op(Inc, "inc", Absolute, 6, false), // 0xee
+    ill(Isc, "isc", Absolute, 6, false), // 0xef
+    op(Beq, "beq", Relative, 2, false), // 0xf0
+    op(Sbc, "sbc", IndirectY, 5, true), // 0xf1
+    ill(Kil, "kil", Implied, 2, false), // 0xf2
+    ill(Isc, "isc", IndirectY, 8, false), // 0xf3
+    ill(Nop, "nop", ZeroPageX, 4, false), // 0xf4
+    op(Sbc, "sbc", ZeroPageX, 4, false), // 0xf5
+    op(Inc, "inc", ZeroPageX, 6, false), // 0xf6
+    ill(Isc, "isc", ZeroPageX, 6, false), // 0xf7
+    op(Sed, "sed", Implied, 2, false), // 0xf8
+    op(Sbc, "sbc", AbsoluteY, 4, true), // 0xf9
+    ill(Nop, "nop", Implied, 2, false), // 0xfa
+    ill(Isc, "isc", AbsoluteY, 7, false), // 0xfb
+    ill(Nop, "nop", AbsoluteX, 4, true), // 0xfc
+    op(Sbc, "sbc", AbsoluteX, 4, true), // 0xfd
+    op(Inc, "inc", AbsoluteX, 7, false), // 0xfe
+    ill(Isc, "isc", AbsoluteX, 7, false), // 0xff
+];
+
+// Returns the text of the instruction at pc, and its length
+pub fn disassemble(mmu: &mut Mmu, pc: u16) -> (String, u16) {
+    let opcode = &OPCODES[mmu.read_u8(pc) as usize];
+    let arg1 = mmu.read_u8(pc.wrapping_add(1));
+    let arg2 = mmu.read_u8(pc.wrapping_add(2));
+    let addr = ((arg2 as u16) << 8) + (arg1 as u16);
+
+    let operand = match opcode.mode {
+        Implied     => String::new(),
+        Accumulator => String::from(" a"),
+        Immediate   => format!(" #${0:02x}", arg1),
+        ZeroPage    => format!(" ${0:02x}", arg1),
+        ZeroPageX   => format!(" ${0:02x},x", arg1),
+        ZeroPageY   => format!(" ${0:02x},y", arg1),
+        Absolute    => format!(" ${0:04x}", addr),
+        AbsoluteX   => format!(" ${0:04x},x", addr),
+        AbsoluteY   => format!(" ${0:04x},y", addr),
+        Indirect    => format!(" (${0:04x})", addr),
+        IndirectX   => format!(" (${0:02x},x)", arg1),
+        IndirectY   => format!(" (${0:02x}),y", arg1),
+        Relative    => format!(" ${0:04x}", pc.wrapping_add(2).wrapping_add(arg1 as i8 as u16))
+    };
+
+    // Unofficial opcodes are marked the same way nestest.log marks them
+    let marker = if opcode.unofficial { "*" } else { "" };
+    
+    (format!("{}{}{}", marker, opcode.name, operand), opcode.mode.length())
+}
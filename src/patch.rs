@@ -0,0 +1,297 @@
+use crc32fast;
+
+use cart::LoadError;
+
+// The most a UPS or BPS patch may grow a ROM to, whatever its header says.
+// Small ROMs can still be expanded to a large board's size.
+const MAX_GROWTH : usize = 16;
+const MIN_TARGET_LIMIT : usize = 1 << 20;
+const MAX_TARGET_SIZE : usize = 64 << 20;
+
+// Soft patches, applied to a ROM image as it's loaded.  The format is
+// told by the patch's magic number.
+pub fn apply(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, LoadError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, rom)
+    }
+    else if patch.starts_with(b"UPS1") {
+        apply_ups(patch, rom)
+    }
+    else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, rom)
+    }
+    else {
+        Err(bad_patch("not an IPS, UPS or BPS patch"))
+    }
+}
+
+fn bad_patch(reason: &str) -> LoadError {
+    LoadError::BadPatch(String::from(reason))
+}
+
+// Walks through a patch, failing instead of running off the end
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> PatchReader<'a> {
+    fn read_u8(&mut self) -> Result<u8, LoadError> {
+        match self.data.get(self.pos) {
+            Some(&b) => {
+                self.pos += 1;
+                Ok(b)
+            },
+            None => Err(bad_patch("ends early"))
+        }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        if count > self.data.len() - self.pos {
+            return Err(bad_patch("ends early"));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn read_u16_be(&mut self) -> Result<usize, LoadError> {
+        let bytes = try!(self.read_bytes(2));
+        Ok(((bytes[0] as usize) << 8) + bytes[1] as usize)
+    }
+
+    fn read_u24_be(&mut self) -> Result<usize, LoadError> {
+        let bytes = try!(self.read_bytes(3));
+        Ok(((bytes[0] as usize) << 16) + ((bytes[1] as usize) << 8) + bytes[2] as usize)
+    }
+
+    // UPS and BPS numbers: 7 bits a byte, low first, with the top bit
+    // marking the last byte
+    fn read_number(&mut self) -> Result<usize, LoadError> {
+        let mut number : usize = 0;
+        let mut shift : usize = 1;
+
+        loop {
+            let b = try!(self.read_u8());
+            number = try!(((b & 0x7f) as usize).checked_mul(shift)
+                .and_then(|n| number.checked_add(n))
+                .ok_or_else(|| bad_patch("number too large")));
+            if (b & 0x80) == 0x80 {
+                return Ok(number);
+            }
+            shift = try!(shift.checked_mul(0x80).ok_or_else(|| bad_patch("number too large")));
+            number = try!(number.checked_add(shift).ok_or_else(|| bad_patch("number too large")));
+        }
+    }
+}
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) + ((bytes[1] as u32) << 8) + ((bytes[2] as u32) << 16) + ((bytes[3] as u32) << 24)
+}
+
+// IPS: records of a 24 bit offset and 16 bit length followed by the
+// data, or a zero length and a run of one byte.  "EOF" ends the records
+// and can be followed by the size to truncate the ROM to.
+fn apply_ips(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let mut reader = PatchReader { data: patch, pos: 5 };
+    let mut out = rom.to_vec();
+
+    loop {
+        if reader.data[reader.pos..].starts_with(b"EOF") {
+            reader.pos += 3;
+            break;
+        }
+
+        let offset = try!(reader.read_u24_be());
+        let length = try!(reader.read_u16_be());
+
+        if length == 0 {
+            let run_length = try!(reader.read_u16_be());
+            let value = try!(reader.read_u8());
+            if out.len() < offset + run_length {
+                out.resize(offset + run_length, 0);
+            }
+            for b in &mut out[offset..offset + run_length] {
+                *b = value;
+            }
+        }
+        else {
+            let data = try!(reader.read_bytes(length));
+            if out.len() < offset + length {
+                out.resize(offset + length, 0);
+            }
+            out[offset..offset + length].copy_from_slice(data);
+        }
+    }
+
+    if reader.pos + 3 <= patch.len() {
+        let size = try!(reader.read_u24_be());
+        out.truncate(size);
+    }
+
+    Ok(out)
+}
+
+// UPS and BPS end with CRC32s of the source, the target and the patch
+// itself.  The patch's own checksum is checked before anything else.
+fn check_footer(patch: &[u8], format: &str) -> Result<(u32, u32), LoadError> {
+    if patch.len() < 16 {
+        return Err(bad_patch("too short"));
+    }
+
+    let footer = &patch[patch.len() - 12..];
+    let expected = read_u32_le(&footer[8..12]);
+    let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+    if expected != actual {
+        return Err(bad_patch(&format!("{} patch is corrupt (CRC32 {:08x}, expected {:08x})", format, actual, expected)));
+    }
+
+    Ok((read_u32_le(&footer[0..4]), read_u32_le(&footer[4..8])))
+}
+
+// The target size is read before anything is checked against it, so
+// don't allocate whatever a corrupt or hostile patch asks for
+fn check_target_size(source_size: usize, target_size: usize) -> Result<(), LoadError> {
+    let limit = source_size.saturating_mul(MAX_GROWTH).clamp(MIN_TARGET_LIMIT, MAX_TARGET_SIZE);
+    if target_size > limit {
+        return Err(bad_patch(&format!("makes a {} byte ROM from {} bytes", target_size, source_size)));
+    }
+
+    Ok(())
+}
+
+fn check_crc(data: &[u8], expected: u32, what: &str) -> Result<(), LoadError> {
+    let actual = crc32fast::hash(data);
+    if actual != expected {
+        return Err(bad_patch(&format!("{} has CRC32 {:08x}, the patch expects {:08x}", what, actual, expected)));
+    }
+
+    Ok(())
+}
+
+// UPS: runs of bytes XORed into the ROM, each after a gap from the last
+fn apply_ups(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let (source_crc, target_crc) = try!(check_footer(patch, "UPS"));
+    try!(check_crc(rom, source_crc, "ROM"));
+
+    let mut reader = PatchReader { data: &patch[..patch.len() - 12], pos: 4 };
+    let source_size = try!(reader.read_number());
+    let target_size = try!(reader.read_number());
+    if source_size != rom.len() {
+        return Err(bad_patch(&format!("ROM is {} bytes, the patch expects {}", rom.len(), source_size)));
+    }
+    try!(check_target_size(source_size, target_size));
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+
+    let mut offset : usize = 0;
+    while reader.pos < reader.data.len() {
+        let gap = try!(reader.read_number());
+        offset = try!(offset.checked_add(gap).ok_or_else(|| bad_patch("writes past the end of the ROM")));
+        loop {
+            let x = try!(reader.read_u8());
+            if offset < out.len() {
+                out[offset] ^= x;
+            }
+            offset = try!(offset.checked_add(1).ok_or_else(|| bad_patch("writes past the end of the ROM")));
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    try!(check_crc(&out, target_crc, "Patched ROM"));
+    Ok(out)
+}
+
+// Adds a signed offset, sign in the low bit, to a position in a buffer
+fn relative(position: usize, encoded: usize, limit: usize) -> Result<usize, LoadError> {
+    let distance = encoded >> 1;
+    let result =
+        if (encoded & 1) == 1 {
+            position.checked_sub(distance)
+        }
+        else {
+            position.checked_add(distance)
+        };
+
+    match result {
+        Some(p) if p <= limit => Ok(p),
+        _ => Err(bad_patch("copies from outside the ROM"))
+    }
+}
+
+// BPS: the target is built by copying from the source, from the patch,
+// or from earlier in the target
+fn apply_bps(patch: &[u8], rom: &[u8]) -> Result<Vec<u8>, LoadError> {
+    let (source_crc, target_crc) = try!(check_footer(patch, "BPS"));
+    try!(check_crc(rom, source_crc, "ROM"));
+
+    let mut reader = PatchReader { data: &patch[..patch.len() - 12], pos: 4 };
+    let source_size = try!(reader.read_number());
+    let target_size = try!(reader.read_number());
+    let metadata_size = try!(reader.read_number());
+    try!(reader.read_bytes(metadata_size));
+    if source_size != rom.len() {
+        return Err(bad_patch(&format!("ROM is {} bytes, the patch expects {}", rom.len(), source_size)));
+    }
+    try!(check_target_size(source_size, target_size));
+
+    let mut out : Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while reader.pos < reader.data.len() {
+        let action = try!(reader.read_number());
+        let length = (action >> 2) + 1;
+        if out.len() + length > target_size {
+            return Err(bad_patch("writes past the end of the ROM"));
+        }
+
+        match action & 0x3 {
+            0 => {
+                let start = out.len();
+                match rom.get(start..start + length) {
+                    Some(bytes) => out.extend_from_slice(bytes),
+                    None => return Err(bad_patch("copies from outside the ROM"))
+                }
+            },
+            1 => {
+                let bytes = try!(reader.read_bytes(length));
+                out.extend_from_slice(bytes);
+            },
+            2 => {
+                let encoded = try!(reader.read_number());
+                source_offset = try!(relative(source_offset, encoded, rom.len()));
+                match rom.get(source_offset..source_offset + length) {
+                    Some(bytes) => out.extend_from_slice(bytes),
+                    None => return Err(bad_patch("copies from outside the ROM"))
+                }
+                source_offset += length;
+            },
+            _ => {
+                let encoded = try!(reader.read_number());
+                target_offset = try!(relative(target_offset, encoded, out.len()));
+                if target_offset >= out.len() {
+                    return Err(bad_patch("copies from outside the ROM"));
+                }
+
+                // The copy can overlap what it's writing, so go a byte at a time
+                for _ in 0..length {
+                    let b = out[target_offset];
+                    out.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(bad_patch("leaves the ROM unfinished"));
+    }
+
+    try!(check_crc(&out, target_crc, "Patched ROM"));
+    Ok(out)
+}
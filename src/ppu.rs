@@ -1,10 +1,31 @@
 use std::fmt; //for custom Debug
+use std::io;
+
+use mapper::Mapper;
+use cart::region;
 
 pub mod mirroring {
     pub const HORIZONTAL  : u8 = 1;
     pub const VERTICAL    : u8 = 2;
     pub const FOUR_SCREEN : u8 = 3;
-    pub const ONE_SCREEN  : u8 = 4;
+    pub const ONE_SCREEN_LOW  : u8 = 4;
+    pub const ONE_SCREEN_HIGH : u8 = 5;
+}
+
+// Maps a $2000-$2FFF address onto the nametable RAM.  The console has
+// room for two nametables, which the cart wires up as the four the PPU
+// addresses.  Four screen carts bring their own RAM for the other two.
+pub fn name_table_offset(mirroring: u8, address: usize) -> usize {
+    let table = (address >> 10) & 0x3;
+    let page = match mirroring {
+        mirroring::HORIZONTAL => table >> 1,
+        mirroring::VERTICAL => table & 0x1,
+        mirroring::ONE_SCREEN_LOW => 0,
+        mirroring::ONE_SCREEN_HIGH => 1,
+        _ => table
+    };
+
+    page * 0x400 + (address & 0x3ff)
 }
 
 const NES_PALETTE : [u32; 64] = [
@@ -18,7 +39,29 @@ const NES_PALETTE : [u32; 64] = [
     0xFFD9A2, 0xCCE199, 0xAEEEB7, 0xAAF7EE, 0xB3EEFF, 0xDDDDDD, 0x111111, 0x111111
 ];
 
+// Reads a .pal file: 64 colours of 3 bytes, R G B.  Files with the
+// emphasis colours after them are fine, only the first 64 are used.
+pub fn parse_palette(data: &[u8]) -> Result<[u32; 64], io::Error> {
+    if data.len() < 64 * 3 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Palette is {} bytes, it needs at least 192", data.len())));
+    }
+
+    let mut palette = [0; 64];
+    for (colour, rgb) in palette.iter_mut().zip(data.chunks(3)) {
+        *colour = ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | (rgb[2] as u32);
+    }
+
+    Ok(palette)
+}
+
 pub type BitsPerPixel = u32;
+
+pub const DOTS_PER_LINE : usize = 341;
+
+// Boards that count A12 rises ignore ones that come after it has only been
+// low briefly, as it is between tile fetches.  About three CPU cycles.
+const A12_FILTER_DOTS : u32 = 9;
  
 pub struct Ppu {
     execute_nmi_on_vblank: bool,
@@ -27,7 +70,6 @@ pub struct Ppu {
     background_address: usize,
     sprite_address: usize,
     ppu_address_increment: usize,
-    name_table_address: usize,
     
     monochrome_display: bool,
     no_background_clipping: bool,
@@ -40,12 +82,15 @@ pub struct Ppu {
     sprite_0_hit: bool,
     sprite_0_buffer: Vec<i32>,
     
-    vram_rw_addr: usize,
-    prev_vram_rw_addr: usize,
-    vram_hi_lo_toggle: u8,
+    // The internal registers $2005 and $2006 share, as laid out in loopy's
+    // "The skinny on NES scrolling".  v and t are 15 bits:
+    // yyy NN YYYYY XXXXX, fine Y, name table, coarse Y and coarse X.
+    // Rendering reads from v, and copies parts of t into it as it goes.
+    vram_addr: u16,
+    temp_vram_addr: u16,
+    fine_x: u8,
+    write_toggle: bool,
     vram_read_buffer: u8,
-    scroll_v: u8,
-    scroll_h: u8,
     
     //FIXME: these are public for debugging purposes
     pub current_scanline: usize,
@@ -53,49 +98,93 @@ pub struct Ppu {
     
     pub sprite_ram: Vec<u8>,
     sprite_ram_address: usize,
-    sprites_crossed: i32,
+    sprite_overflow: bool,
+
+    // The sprites on the line being drawn or fetched for, as they're found
+    // in sprite_ram.  Past the eighth they go in extra_sprites, which is
+    // only used when no_sprite_limit is set.
+    secondary_oam: [u8; 32],
+    extra_sprites: Vec<u8>,
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+    // Draw every sprite on a line, not just the first 8, to get rid of the
+    // flicker games use to show more.  The overflow flag is still set as
+    // the hardware would.
+    pub no_sprite_limit: bool,
     
     pub offscreen_buffer: Vec<BitsPerPixel>,
+    pub palette: [u32; 64],
+
+    // Draw whole lines at once with render_scanline, rather than a dot at
+    // a time alongside the CPU.  Quicker, but changes made mid-line are
+    // only seen from the next one.
+    pub fast_render: bool,
+    pub dot: usize,
+    // Set when the dot renderer moves onto a new line, until the CPU has
+    // been told its line is over
+    pub scanline_ended: bool,
+    nmi_pending: bool,
+    // Set on entering vblank, until the frame is counted.  Catches the
+    // frame's end even when a long step, like sprite DMA, runs right past
+    // the line it happened on.
+    frame_complete: bool,
+
+    // Where the region's frames start vblank and end.  NTSC frames are 262
+    // lines, PAL and Dendy ones 312, and Dendy waits 50 lines longer to
+    // start vblank.  Only NTSC shortens odd frames.
+    vblank_line: usize,
+    pre_render_line: usize,
+    short_odd_frames: bool,
+
+    // The status register's vblank flag.  Set on dot 1 of the vblank line,
+    // and cleared by reading $2002 or on the pre-render line.
+    vblank: bool,
+    // Reading $2002 just as vblank starts stops the flag, and the NMI with
+    // it, for the rest of the frame
+    vblank_suppressed: bool,
+    // Odd frames are a dot shorter while rendering
+    odd_frame: bool,
+
+    // The background pipeline: the tile being fetched, and shift registers
+    // holding the tile being drawn in their high byte and the next in
+    // their low byte
+    next_tile: u8,
+    next_attribute: u8,
+    next_pattern_low: u8,
+    next_pattern_high: u8,
+    pattern_shift_low: u16,
+    pattern_shift_high: u16,
+    attribute_shift_low: u16,
+    attribute_shift_high: u16,
+
+    // The patterns for the sprites on this line, fetched at the end of the
+    // last one.  Each waits out a counter to its X, then shifts its
+    // pattern out.
+    sprite_pattern_low: [u8; 64],
+    sprite_pattern_high: [u8; 64],
+    sprite_attributes: [u8; 64],
+    sprite_x: [u8; 64],
 
-    //From cart
-    pub chr_rom : Vec<Vec<u8>>,
-    pub mirroring: u8,
-    pub mirroring_base: usize, 
-    pub is_vram: bool,
-    pub mapper: u8,
-    pub num_chr_pages: usize,
-
-    pub active_chr_page: Vec<usize>,
-
-    //workarounds
-    pub fix_scroll_offset_1: bool,
-    pub fix_scroll_offset_2: bool,
-    pub fix_scroll_offset_3: bool,
-    pub fix_bg_change: bool,
-    pub fix_scroll_reset: bool,
+    a12_high: bool,
+    a12_low_dots: u32
 }
 
 impl fmt::Debug for Ppu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "nmi: {0} size: {1} bg: {2:04x} sp: {3:04x} inc: {4} 0_hit: {5} rw: {6:04x}\nline: {7} hilo: {8} scrv: {9} srch: {10} {11}{12}{13}{14}{15}",
+        write!(f, "nmi: {0} size: {1} bg: {2:04x} sp: {3:04x} inc: {4} 0_hit: {5} v: {6:04x}\nline: {7} dot: {16} w: {8} t: {9:04x} x: {10} {11}{12}{13}{14}{15}",
             self.execute_nmi_on_vblank, self.sprite_size, self.background_address, self.sprite_address,
-            self.ppu_address_increment, self.sprite_0_hit, self.vram_rw_addr, self.current_scanline,
-            self.vram_hi_lo_toggle, self.scroll_v, self.scroll_h,
+            self.ppu_address_increment, self.sprite_0_hit, self.vram_addr, self.current_scanline,
+            self.write_toggle as u8, self.temp_vram_addr, self.fine_x,
             if self.monochrome_display {'M'} else {'-'},
             if self.no_background_clipping {'D'} else {'-'},
             if self.no_sprite_clipping {'E'} else {'-'},
             if self.background_visible {'B'} else {'-'},
-            if self.sprites_visible {'S'} else {'-'}) 
+            if self.sprites_visible {'S'} else {'-'}, self.dot)
     }
 }
 
 impl Ppu {
     pub fn new() -> Ppu {
-        let mut active_chr_page: Vec<usize> = Vec::new();
-        for x in 0..8 {
-            active_chr_page.push(x);
-        }
-
         Ppu {
             execute_nmi_on_vblank: false,
             ppu_master: 0xff,
@@ -103,60 +192,74 @@ impl Ppu {
             background_address: 0x0000,
             sprite_address: 0x0000,
             ppu_address_increment: 1,
-            name_table_address: 0x2000,
             current_scanline: 0,
-            vram_hi_lo_toggle: 1,
+            vram_addr: 0,
+            temp_vram_addr: 0,
+            fine_x: 0,
+            write_toggle: false,
             vram_read_buffer: 0,
-            prev_vram_rw_addr: 0,
-            vram_rw_addr: 0,
             sprite_ram_address: 0,
-            scroll_v: 0,
-            scroll_h: 0,
             ppu_color: 0,
-            sprites_crossed: 0,
+            sprite_overflow: false,
+            secondary_oam: [0xff; 32],
+            extra_sprites: Vec::new(),
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            no_sprite_limit: false,
             sprite_0_hit: false,
             monochrome_display: false,
             no_background_clipping: false,
             no_sprite_clipping: false,
             background_visible: false,
             sprites_visible: false,
-            fix_scroll_offset_1: false,
-            fix_scroll_offset_2: false,
-            fix_scroll_offset_3: false,
-            fix_bg_change: false,
-            fix_scroll_reset: false,
             name_tables: vec![0; 0x2000],
             sprite_ram: vec![0; 0x100],
             offscreen_buffer: vec![0; 256*240],
+            palette: NES_PALETTE,
             sprite_0_buffer: vec![0; 256],
-            chr_rom : Vec::new(),
-            mirroring: mirroring::HORIZONTAL,
-            mirroring_base: 0, 
-            is_vram: false,
-            mapper: 0,
-            num_chr_pages: 0,
-            active_chr_page: active_chr_page
+            fast_render: false,
+            dot: 0,
+            scanline_ended: false,
+            nmi_pending: false,
+            frame_complete: false,
+            vblank_line: 241,
+            pre_render_line: 261,
+            short_odd_frames: true,
+            vblank: false,
+            vblank_suppressed: false,
+            odd_frame: false,
+            next_tile: 0,
+            next_attribute: 0,
+            next_pattern_low: 0,
+            next_pattern_high: 0,
+            pattern_shift_low: 0,
+            pattern_shift_high: 0,
+            attribute_shift_low: 0,
+            attribute_shift_high: 0,
+            sprite_pattern_low: [0; 64],
+            sprite_pattern_high: [0; 64],
+            sprite_attributes: [0; 64],
+            sprite_x: [0; 64],
+            a12_high: false,
+            a12_low_dots: 0
         }
     }
     
     pub fn control_reg_1_write(&mut self, data: u8) {
+        // NMI is raised on the edge, so turning it on during vblank raises
+        // it straight away, and again each time it's turned back on
+        let nmi_was_enabled = self.execute_nmi_on_vblank;
         self.execute_nmi_on_vblank = (data & 0x80) == 0x80;
+        if self.vblank && self.execute_nmi_on_vblank && !nmi_was_enabled {
+            self.nmi_pending = true;
+        }
         self.sprite_size = if (data & 0x20) == 0x20 {16} else {8};
         self.background_address = if (data & 0x10) == 0x10 {0x1000} else {0};
         self.sprite_address = if (data & 0x8) == 0x8 {0x1000} else {0};
         self.ppu_address_increment = if (data & 0x4) == 0x4 {32} else {1};
         
-        match data & 0x3 {
-            0 => self.name_table_address = 0x2000,
-            1 => self.name_table_address = 0x2400,
-            2 => self.name_table_address = 0x2800,
-            3 => self.name_table_address = 0x2c00,
-            _ => {}
-        }
-        
-        if self.fix_bg_change && self.current_scanline == 241 {
-            self.name_table_address = 0x2000;
-        }
+        // The name table select goes to t
+        self.temp_vram_addr = (self.temp_vram_addr & !0x0c00) | (((data & 0x3) as u16) << 10);
         
         if self.ppu_master == 0xff {
             if (data & 0x40) == 0x40 {
@@ -180,156 +283,169 @@ impl Ppu {
     pub fn status_reg_read(&mut self) -> u8 {
         let mut result: u8 = 0;
         
-        if self.current_scanline >= 240 {
+        // A read the dot before vblank starts sees the flag clear, one on
+        // the dot it's set sees it set, and either stops it being set this
+        // frame.  One on the dot after still stops the NMI.
+        if self.current_scanline == self.vblank_line && !self.fast_render {
+            match self.dot {
+                0 => self.vblank_suppressed = true,
+                1 => {
+                    self.vblank_suppressed = true;
+                    result += 0x80;
+                },
+                2 => self.nmi_pending = false,
+                _ => {}
+            }
+        }
+        
+        if self.vblank {
             result += 0x80;
+            self.vblank = false;
         }
         
         if self.sprite_0_hit {
             result += 0x40;
         }
         
-        if self.sprites_crossed > 8 {
+        if self.sprite_overflow {
             result += 0x20;
         }
         
-        self.vram_hi_lo_toggle = 1;
+        self.write_toggle = false;
         
         result
     }
     
+    // $2005: X scroll then Y scroll, both into t
     pub fn vram_addr_reg_1_write(&mut self, data: u8) {
-        if self.vram_hi_lo_toggle == 1 {
-            self.scroll_v = data;
-            self.vram_hi_lo_toggle = 0;
+        if !self.write_toggle {
+            self.temp_vram_addr = (self.temp_vram_addr & !0x001f) | ((data >> 3) as u16);
+            self.fine_x = data & 0x7;
         }
         else {
-            self.scroll_h = data;
-            if self.scroll_h > 239 {
-                self.scroll_h = 0;
-            }
-            
-            // FIXME: these workarounds are from original emu
-            // Unsure if there are unimplemented
-            // parts of the PPU that should be added
-            if self.fix_scroll_offset_1 && self.current_scanline < 240 {                
-                self.scroll_h = ((self.scroll_h as i32) - self.current_scanline as i32) as u8;
-            }
-            if self.fix_scroll_offset_2 && self.current_scanline < 240 {                
-                self.scroll_h = ((self.scroll_h as i32) - (self.current_scanline as i32) + 8) as u8;
-            }
-            if self.fix_scroll_offset_3 && self.current_scanline < 240 {                
-                self.scroll_h = 238;
-            }
-        
-            self.vram_hi_lo_toggle = 1;
+            self.temp_vram_addr = (self.temp_vram_addr & !0x73e0) |
+                (((data & 0x7) as u16) << 12) | (((data >> 3) as u16) << 5);
         }
+        self.write_toggle = !self.write_toggle;
     }
     
+    // $2006: the high byte then the low byte of t, which is then copied to v
     pub fn vram_addr_reg_2_write(&mut self, data: u8) {
-        if self.vram_hi_lo_toggle == 1 {
-            self.prev_vram_rw_addr = 
-                self.vram_rw_addr;
-            self.vram_rw_addr = (data as usize) << 8;
-            self.vram_hi_lo_toggle = 0;
+        if !self.write_toggle {
+            self.temp_vram_addr = (self.temp_vram_addr & 0x00ff) | (((data & 0x3f) as u16) << 8);
         }
         else {
-            self.vram_rw_addr += data as usize;
-            
-            if (self.prev_vram_rw_addr == 0) && (self.current_scanline < 240) {
-                //check for scrolling trick
-                
-                if (self.vram_rw_addr >= 0x2000) && (self.vram_rw_addr <= 0x2400) {
-                    self.scroll_h = (((self.vram_rw_addr as i32 - 0x2000) / 0x20) * 8 - self.current_scanline as i32) as u8;
-                }
-                
-            }
-            self.vram_hi_lo_toggle = 1;
+            self.temp_vram_addr = (self.temp_vram_addr & 0xff00) | (data as u16);
+            self.vram_addr = self.temp_vram_addr;
         }
+        self.write_toggle = !self.write_toggle;
+    }
+
+    // Palette entries 0, 4, 8 and C are shared by the background and sprites
+    fn palette_index(address: usize) -> usize {
+        let index = address & 0x1f;
+        if (index & 0x13) == 0x10 { index & 0x0f } else { index }
     }
     
-    pub fn vram_io_reg_write(&mut self, data: u8) {
-        if self.vram_rw_addr < 0x2000 {
-            let vram_rw_addr = self.vram_rw_addr;
-            self.write_chr_rom(vram_rw_addr, data);
-        }
-        else if (self.vram_rw_addr >= 0x2000) && (self.vram_rw_addr < 0x3f00) {
-            match self.mirroring {
-                mirroring::HORIZONTAL => {
-                    match self.vram_rw_addr & 0x2c00 {
-                        0x2000 => self.name_tables[self.vram_rw_addr - 0x2000] = data,
-                        0x2400 => self.name_tables[self.vram_rw_addr - 0x2400] = data,
-                        0x2800 => self.name_tables[self.vram_rw_addr - 0x2400] = data,
-                        0x2C00 => self.name_tables[self.vram_rw_addr - 0x2800] = data,
-                        _ => println!("Unknown VRAM write: {0:04x}", self.vram_rw_addr)
-                    }
-                },
-                mirroring::VERTICAL => {
-                    match self.vram_rw_addr & 0x2c00 {
-                        0x2000 => self.name_tables[self.vram_rw_addr - 0x2000] = data,
-                        0x2400 => self.name_tables[self.vram_rw_addr - 0x2000] = data,
-                        0x2800 => self.name_tables[self.vram_rw_addr - 0x2800] = data,
-                        0x2C00 => self.name_tables[self.vram_rw_addr - 0x2800] = data,
-                        _ => println!("Unknown VRAM write: {0:04x}", self.vram_rw_addr)
-                    }
-                },
-                mirroring::ONE_SCREEN => {
-                    if self.mirroring_base == 0x2000 {
-                        match self.vram_rw_addr & 0x2c00 {
-                            0x2000 => self.name_tables[self.vram_rw_addr - 0x2000] = data,
-                            0x2400 => self.name_tables[self.vram_rw_addr - 0x2400] = data,
-                            0x2800 => self.name_tables[self.vram_rw_addr - 0x2800] = data,
-                            0x2C00 => self.name_tables[self.vram_rw_addr - 0x2c00] = data,
-                            _ => println!("Unknown VRAM write: {0:04x}", self.vram_rw_addr)
-                        }
-                    }
-                    else if self.mirroring_base == 0x2400 {
-                        match self.vram_rw_addr & 0x2c00 {
-                            0x2000 => self.name_tables[self.vram_rw_addr + 0x400 - 0x2000] = data,
-                            0x2400 => self.name_tables[self.vram_rw_addr - 0x2000] = data,
-                            0x2800 => self.name_tables[self.vram_rw_addr - 0x2400] = data,
-                            0x2C00 => self.name_tables[self.vram_rw_addr - 0x2800] = data,
-                            _ => println!("Unknown VRAM write: {0:04x}", self.vram_rw_addr)
-                        }
-                    }
-                },
-                _ =>self.name_tables[self.vram_rw_addr - 0x2000] = data
-            }
+    pub fn vram_io_reg_write(&mut self, mapper: &mut dyn Mapper, data: u8) {
+        let address = (self.vram_addr & 0x3fff) as usize;
+
+        if address < 0x2000 {
+            mapper.ppu_write(address as u16, data);
+        }
+        else if address < 0x3f00 {
+            self.name_tables[mapper.name_table_offset(address)] = data;
         }
-        else if (self.vram_rw_addr >= 0x3f00) && (self.vram_rw_addr < 0x3f20) {
-            self.name_tables[self.vram_rw_addr - 0x2000] = data;
-            if (self.vram_rw_addr & 0x7) == 0 {
-                self.name_tables[(self.vram_rw_addr - 0x2000) ^ 0x10] = data;
+        else {
+            let index = Ppu::palette_index(address);
+            self.name_tables[0x1f00 + index] = data;
+            if (index & 0x3) == 0 {
+                self.name_tables[0x1f00 + (index ^ 0x10)] = data;
             }
         }
-        self.vram_rw_addr += self.ppu_address_increment;
+        self.vram_addr = self.vram_addr.wrapping_add(self.ppu_address_increment as u16) & 0x7fff;
     }
     
-    pub fn vram_io_reg_read(&mut self) -> u8 {
-        let mut result = 0;
+    pub fn vram_io_reg_read(&mut self, mapper: &mut dyn Mapper) -> u8 {
+        let address = (self.vram_addr & 0x3fff) as usize;
+        let result;
         
-        if self.vram_rw_addr < 0x3f00 {
+        if address < 0x3f00 {
             result = self.vram_read_buffer;
             
-            if self.vram_rw_addr >= 0x2000 {
-                self.vram_read_buffer = self.name_tables[self.vram_rw_addr - 0x2000];                
+            if address >= 0x2000 {
+                self.vram_read_buffer = self.name_tables[mapper.name_table_offset(address)];
             }
             else {
-                self.vram_read_buffer = self.read_chr_rom(self.vram_rw_addr);
+                self.vram_read_buffer = mapper.ppu_read(address as u16);
             }
         }
-        else if self.vram_rw_addr >= 0x4000 {
-            println!("Error: Need VRAM mirroring!");
-        }
         else {
-            result = self.name_tables[self.vram_rw_addr - 0x2000];
+            // Palette reads aren't buffered, but the buffer is filled from
+            // the name table underneath
+            result = self.name_tables[0x1f00 + Ppu::palette_index(address)];
+            self.vram_read_buffer = self.name_tables[mapper.name_table_offset(address - 0x1000)];
         }
         
-        //FIXME: This is not entirely accurate, the 'buffered' read
-        //should not increment the address the first time
-        self.vram_rw_addr += self.ppu_address_increment;
+        self.vram_addr = self.vram_addr.wrapping_add(self.ppu_address_increment as u16) & 0x7fff;
         
         result
     }
+
+    // Takes a cart::region value
+    pub fn set_region(&mut self, region: u8) {
+        let (vblank_line, pre_render_line, short_odd_frames) = match region {
+            region::PAL => (241, 311, false),
+            region::DENDY => (291, 311, false),
+            _ => (241, 261, true)
+        };
+        self.vblank_line = vblank_line;
+        self.pre_render_line = pre_render_line;
+        self.short_odd_frames = short_odd_frames;
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.background_visible || self.sprites_visible
+    }
+
+    // Moves v down a line at the end of each visible one, from fine Y into
+    // coarse Y and then into the next name table down.  Coarse Y wraps at
+    // 30 rows, or at 32 without switching tables when it's been set into
+    // the attribute bytes.
+    fn increment_y(&mut self) {
+        let mut v = self.vram_addr;
+
+        if (v & 0x7000) != 0x7000 {
+            v += 0x1000;
+        }
+        else {
+            v &= !0x7000;
+            let mut coarse_y = (v & 0x03e0) >> 5;
+            if coarse_y == 29 {
+                coarse_y = 0;
+                v ^= 0x0800;
+            }
+            else if coarse_y == 31 {
+                coarse_y = 0;
+            }
+            else {
+                coarse_y += 1;
+            }
+            v = (v & !0x03e0) | (coarse_y << 5);
+        }
+
+        self.vram_addr = v;
+    }
+
+    // Coarse X and the horizontal name table bit
+    fn copy_horizontal_scroll(&mut self) {
+        self.vram_addr = (self.vram_addr & !0x041f) | (self.temp_vram_addr & 0x041f);
+    }
+
+    // Fine Y, coarse Y and the vertical name table bit
+    fn copy_vertical_scroll(&mut self) {
+        self.vram_addr = (self.vram_addr & !0x7be0) | (self.temp_vram_addr & 0x7be0);
+    }
     
     pub fn sprite_ram_addr_reg_write(&mut self, data: u8) {
         self.sprite_ram_address = data as usize;
@@ -344,219 +460,86 @@ impl Ppu {
         self.sprite_ram[self.sprite_ram_address]
     }
 
-    pub fn write_chr_rom(&mut self, addr: usize, data:u8) {
-        if self.is_vram {
-            match addr {
-                0x0000...0x03ff => self.chr_rom[self.active_chr_page[0]][addr] = data,
-                0x0400...0x07ff => self.chr_rom[self.active_chr_page[1]][addr - 0x400] = data,
-                0x0800...0x0bff => self.chr_rom[self.active_chr_page[2]][addr - 0x800] = data,
-                0x0c00...0x0fff => self.chr_rom[self.active_chr_page[3]][addr - 0xc00] = data,
-                0x1000...0x13ff => self.chr_rom[self.active_chr_page[4]][addr - 0x1000] = data,
-                0x1400...0x17ff => self.chr_rom[self.active_chr_page[5]][addr - 0x1400] = data,
-                0x1800...0x1bff => self.chr_rom[self.active_chr_page[6]][addr - 0x1800] = data,
-                0x1c00...0x1fff => self.chr_rom[self.active_chr_page[7]][addr - 0x1c00] = data,
-                _ => {}
-            }
-        }
-    }
-    
-    pub fn read_chr_rom(&self, addr: usize) -> u8 {
-        if addr < 0x400 {
-            return self.chr_rom[self.active_chr_page[0]][addr];
-        }
-        else if addr < 0x800 {
-            return self.chr_rom[self.active_chr_page[1]][addr - 0x400];
-        }
-        else if addr < 0xc00 {
-            return self.chr_rom[self.active_chr_page[2]][addr - 0x800];
-        }
-        else if addr < 0x1000 {
-            return self.chr_rom[self.active_chr_page[3]][addr - 0xc00];
-        }
-        else if addr < 0x1400 {
-            return self.chr_rom[self.active_chr_page[4]][addr - 0x1000];
-        }
-        else if addr < 0x1800 {
-            return self.chr_rom[self.active_chr_page[5]][addr - 0x1400];
-        }
-        else if addr < 0x1c00 {
-            return self.chr_rom[self.active_chr_page[6]][addr - 0x1800];
-        }
-        else {
-            return self.chr_rom[self.active_chr_page[7]][addr - 0x1c00];
-        }
-    }
-    
-    fn render_background(&mut self) {
-        let mut start_column;
-        let mut end_column;
+    // Draws the line v points at, starting fine X pixels into its first
+    // tile.  33 tiles are fetched to cover the part of a tile that fine X
+    // pushes off the right edge.
+    fn render_background(&mut self, mapper: &mut dyn Mapper) {
+        let mut v = self.vram_addr;
+        let fine_y = ((v >> 12) & 0x7) as usize;
         
-        for v_scroll_side in 0..2 {
-            let mut virtual_scanline = self.current_scanline + self.scroll_h as usize;
-            let mut name_table_base = self.name_table_address;
+        for tile in 0..33 {
+            let tile_num = self.name_tables[mapper.name_table_offset(0x2000 | (v & 0x0fff) as usize)];
             
-            if v_scroll_side == 0 {
-                if virtual_scanline >= 240 {
-                    match self.name_table_address {
-                        0x2000 => name_table_base = 0x2800,
-                        0x2400 => name_table_base = 0x2c00,
-                        0x2800 => name_table_base = 0x2000,
-                        0x2c00 => name_table_base = 0x2400,
-                        _ => {}
-                    }
-                    
-                    virtual_scanline -= 240;
-                }
-                
-                start_column = self.scroll_v / 8;
-                end_column = 32;
-            }
-            else {
-                if virtual_scanline >= 240 {
-                    match self.name_table_address {
-                        0x2000 => name_table_base = 0x2c00,
-                        0x2400 => name_table_base = 0x2800,
-                        0x2800 => name_table_base = 0x2400,
-                        0x2c00 => name_table_base = 0x2000,
-                        _ => {}
-                    }
-                    
-                    virtual_scanline -= 240;
-                }
-                else {
-                    match self.name_table_address {
-                        0x2000 => name_table_base = 0x2400,
-                        0x2400 => name_table_base = 0x2000,
-                        0x2800 => name_table_base = 0x2c00,
-                        0x2c00 => name_table_base = 0x2800,
-                        _ => {}
-                    }         
-                }
-                
-                start_column = 0;
-                end_column = self.scroll_v / 8 + 1;
-            }
+            let tile_data_offset = self.background_address + (tile_num as usize) * 16 + fine_y;
+            let tile_data_1 = mapper.ppu_read(tile_data_offset as u16);
+            let tile_data_2 = mapper.ppu_read((tile_data_offset + 8) as u16);
             
-            match self.mirroring {
-                mirroring::HORIZONTAL => {
-                    match name_table_base {
-                        0x2400 => name_table_base = 0x2000,
-                        0x2800 => name_table_base = 0x2400,
-                        0x2c00 => name_table_base = 0x2400,
-                        _ => {}
-                    }
-                },
-                mirroring::VERTICAL => {
-                    match name_table_base {
-                        0x2800 => name_table_base = 0x2000,
-                        0x2c00 => name_table_base = 0x2400,
-                        _ => {}
-                    }
-                },
-                mirroring::ONE_SCREEN => {
-                    name_table_base = self.mirroring_base;
-                },
-                _ => {}
-            }
+            // Each attribute byte covers 4x4 tiles, two bits per 2x2 of them
+            let attribute_address = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+            let attribute = self.name_tables[mapper.name_table_offset(attribute_address as usize)];
+            let shift = ((v >> 4) & 0x4) | (v & 0x2);
+            let palette_high_bits = ((attribute >> shift) & 0x3) << 2;
             
-            for current_col in start_column..end_column {
-                // grab the bg tile for the given column and scanline
-                
-                let tile_row = virtual_scanline / 8;
-                let tile_offset = virtual_scanline % 8;
-                
-                let tile_num = self.name_tables[name_table_base - 0x2000 + 
-                    (tile_row * 32) + current_col as usize];
-                
-                let tile_data_offset = self.background_address + (tile_num as usize) * 16;
-                
-                let tile_data_1 = self.read_chr_rom(tile_data_offset + tile_offset);
-                let tile_data_2 = self.read_chr_rom(tile_data_offset + tile_offset + 8);
-                    
-                // next, calculate where to go in the palette table
-                
-                let mut palette_high_bits = self.name_tables[((name_table_base - 0x2000 + 
-                    0x3c0 + ((tile_row / 4) * 8) + ((current_col / 4) as usize)))];
-                palette_high_bits = palette_high_bits >> ((4 * ((tile_row % 4) >> 1)) + 
-                    ((current_col % 4) & 0x2) as usize);
-                palette_high_bits = (palette_high_bits & 0x3) << 2;
-                
-                // that was fun, now we have enough to render the tile
-                
-                let mut start_tile_pixel = 0;
-                let mut end_tile_pixel = 8;
-                
-                if (v_scroll_side == 0) && (current_col == start_column) {
-                    start_tile_pixel = self.scroll_v % 8;
-                }
-                else if (v_scroll_side == 1) && (current_col == end_column) {
-                    end_tile_pixel = self.scroll_v % 8;
+            for i in 0..8 {
+                let x = tile * 8 + i;
+                if x < self.fine_x as usize || x - (self.fine_x as usize) >= 256 {
+                    continue;
                 }
+                let x = x - self.fine_x as usize;
+                
+                let pixel_color = palette_high_bits + (((tile_data_2 >> (7 - i)) & 0x1) << 1) + 
+                    ((tile_data_1 >> (7 - i)) & 0x1);
                 
-                for i in start_tile_pixel..end_tile_pixel {
-                    let pixel_color = palette_high_bits + (((tile_data_2 & (1 << (7 - i))) >> (7 - i)) << 1) + 
-                        ((tile_data_1 & (1 << (7 - i))) >> (7 - i)); 
+                if (pixel_color % 4) != 0 {
+                    self.offscreen_buffer[(self.current_scanline * 256) + x] = 
+                        self.palette[(0x3f & self.name_tables[0x1f00 + pixel_color as usize]) as usize];
                     
-                    if (pixel_color % 4) != 0 {
-                        if v_scroll_side == 0 {
-                            self.offscreen_buffer[(self.current_scanline * 256) + ((8 * current_col) as usize) - (self.scroll_v as usize) + i as usize] = 
-                                NES_PALETTE[(0x3f & self.name_tables[0x1f00 + pixel_color as usize]) as usize];
-                            
-                            if !self.sprite_0_hit {
-                                self.sprite_0_buffer[((8 * current_col) - self.scroll_v + (i as u8)) as usize] += 4;
-                            }
-                        }
-                        else {
-                            if ((8 * current_col as usize) + (256 - self.scroll_v as usize) + i as usize) < 256 {
-                                self.offscreen_buffer[((self.current_scanline * 256) + ((8 * current_col) as usize) + ((256usize - self.scroll_v as usize) as usize) + i as usize) as usize] = 
-                                    NES_PALETTE[(0x3f & (self.name_tables[0x1f00 + pixel_color as usize] as usize))];
-                            
-                                //Console.WriteLine("Greater than: {0}", ((8 * currentTileColumn) + (256-scrollV) + i));
-                                if !self.sprite_0_hit {
-                                    self.sprite_0_buffer[((8 * current_col) + ((256 - self.scroll_v as usize) as u8) + i) as usize] += 4;
-                                }
-                            }
-                        }
+                    if !self.sprite_0_hit {
+                        self.sprite_0_buffer[x] += 4;
                     }
                 }
             }
+            
+            // Next tile, into the next name table across at the end of this one
+            if (v & 0x001f) == 31 {
+                v = (v & !0x001f) ^ 0x0400;
+            }
+            else {
+                v += 1;
+            }
         }
     }
     
-    fn render_sprites(&mut self, behind: u8) {
-        let mut i : usize = 252;
-        
-        loop {
-            let actual_y : usize = (self.sprite_ram[i] as usize) + 1;
-            
-            if ((self.sprite_ram[i+2] & 0x20) == behind) && 
-                (actual_y <= self.current_scanline) &&
-                ((actual_y + self.sprite_size) > self.current_scanline) {
+    // Draws the sprites evaluate_sprites found for this line, last first so
+    // the first ends up on top
+    fn render_sprites(&mut self, mapper: &mut dyn Mapper, behind: u8) {
+        for slot in (0..self.sprite_count).rev() {
+            let sprite = self.line_sprite(slot);
+            let actual_y : usize = (sprite[0] as usize) + 1;
             
-                self.sprites_crossed += 1;
-                
+            if (sprite[2] & 0x20) == behind {
                 if self.sprite_size == 8 {
                     //sprite is 8x8
                     
                     let sprite_line_to_draw : usize = 
-                        if (self.sprite_ram[i+2] & 0x80) != 0x80 {
+                        if (sprite[2] & 0x80) != 0x80 {
                             self.current_scanline - actual_y
                         }
                         else {
                             actual_y + 7 - self.current_scanline
                         };
                     let offset_to_sprite : usize = self.sprite_address + 
-                        (((self.sprite_ram[i+1] as usize) * 16) as usize);
+                        (((sprite[1] as usize) * 16) as usize);
                     
-                    let tile_data_1 = self.read_chr_rom(offset_to_sprite + sprite_line_to_draw);
-                    let tile_data_2 = self.read_chr_rom(offset_to_sprite + sprite_line_to_draw + 8);
+                    let tile_data_1 = mapper.ppu_read((offset_to_sprite + sprite_line_to_draw) as u16);
+                    let tile_data_2 = mapper.ppu_read((offset_to_sprite + sprite_line_to_draw + 8) as u16);
                     
-                    let palette_high_bits = (self.sprite_ram[i+2] & 0x3) << 2;
+                    let palette_high_bits = (sprite[2] & 0x3) << 2;
                     
                     for j in 0..8 {
                         // Calculate pixel color, we'll also check the horizontal flip bit
                         let pixel_color = 
-                            if (self.sprite_ram[i+2] & 0x40) == 0x40 {
+                            if (sprite[2] & 0x40) == 0x40 {
                                 palette_high_bits + (((tile_data_2 & (1 << (j))) >> (j)) << 1) + ((tile_data_1 & (1 << (j))) >> (j)) 
                             }
                             else {
@@ -564,12 +547,12 @@ impl Ppu {
                                     ((tile_data_1 & (1 << (7 - j))) >> (7 - j))
                             };
                         if (pixel_color % 4) != 0 {
-                            if ((self.sprite_ram[i+3] as usize) + j) < 256 {
-                                self.offscreen_buffer[(self.current_scanline * 256) + (self.sprite_ram[i+3] as usize) + j] = 
-                                    NES_PALETTE[(0x3f & self.name_tables[0x1f10 + (pixel_color as usize)]) as usize];
+                            if ((sprite[3] as usize) + j) < 256 {
+                                self.offscreen_buffer[(self.current_scanline * 256) + (sprite[3] as usize) + j] = 
+                                    self.palette[(0x3f & self.name_tables[0x1f10 + (pixel_color as usize)]) as usize];
                             
-                                if i == 0 {
-                                    self.sprite_0_buffer[(self.sprite_ram[i+3] as usize) + j] += 1;
+                                if slot == 0 && self.sprite_zero_on_line {
+                                    self.sprite_0_buffer[(sprite[3] as usize) + j] += 1;
                                 }
                             }
                         }
@@ -578,14 +561,14 @@ impl Ppu {
                 else {
                     // If they aren't 8x8, they're 8x16
                     
-                    let sprite_id = self.sprite_ram[i+1] as usize;
+                    let sprite_id = sprite[1] as usize;
                     
                     let mut sprite_line_to_draw : usize = 
-                        if (self.sprite_ram[i+2] & 0x80) != 0x80 {
+                        if (sprite[2] & 0x80) != 0x80 {
                             self.current_scanline - actual_y
                         }
                         else {
-                            actual_y + 16 - self.current_scanline
+                            actual_y + 15 - self.current_scanline
                         };
                     
                     let mut offset_to_sprite : usize = 0;
@@ -612,14 +595,14 @@ impl Ppu {
                         }
                     }
                     
-                    let tile_data_1 = self.read_chr_rom(offset_to_sprite + sprite_line_to_draw);
-                    let tile_data_2 = self.read_chr_rom(offset_to_sprite + sprite_line_to_draw + 8);
+                    let tile_data_1 = mapper.ppu_read((offset_to_sprite + sprite_line_to_draw) as u16);
+                    let tile_data_2 = mapper.ppu_read((offset_to_sprite + sprite_line_to_draw + 8) as u16);
                                         
-                    let palette_high_bits = (self.sprite_ram[i+2] & 0x3) << 2;
+                    let palette_high_bits = (sprite[2] & 0x3) << 2;
                     
                     for j in 0..8 {
                         let pixel_color = 
-                            if (self.sprite_ram[i+2] & 0x40) == 0x40 {
+                            if (sprite[2] & 0x40) == 0x40 {
                                 palette_high_bits + (((tile_data_2 & (1 << (j))) >> (j)) << 1) + ((tile_data_1 & (1 << (j))) >> (j)) 
                             }
                             else {
@@ -628,26 +611,29 @@ impl Ppu {
                             };
                             
                         if (pixel_color % 4) != 0 {
-                            if ((self.sprite_ram[i+3] as usize) + j) < 256 {
-                                self.offscreen_buffer[(self.current_scanline * 256) + (self.sprite_ram[i+3] as usize) + j] = 
-                                    NES_PALETTE[(0x3f & self.name_tables[0x1f10 + (pixel_color as usize)]) as usize];
+                            if ((sprite[3] as usize) + j) < 256 {
+                                self.offscreen_buffer[(self.current_scanline * 256) + (sprite[3] as usize) + j] = 
+                                    self.palette[(0x3f & self.name_tables[0x1f10 + (pixel_color as usize)]) as usize];
                             
-                                if i == 0 {
-                                    self.sprite_0_buffer[(self.sprite_ram[i+3] as usize) + j] += 1;
+                                if slot == 0 && self.sprite_zero_on_line {
+                                    self.sprite_0_buffer[(sprite[3] as usize) + j] += 1;
                                 }
                             }
                         }
                     }                                        
                 }
             }
-            
-            if i == 0 { break; }
-            i -= 4;
         }
     }
     
-    pub fn render_scanline(&mut self) -> bool {
-        if self.current_scanline < 234 {
+    pub fn render_scanline(&mut self, mapper: &mut dyn Mapper) -> bool {
+        // Scroll writes made while the line ran count from its start, as
+        // the CPU's part of a line is run before it's drawn
+        if self.current_scanline < 240 && self.rendering_enabled() {
+            self.copy_horizontal_scroll();
+        }
+
+        if self.current_scanline < 240 {
             if self.name_tables[0x1f00] > 63 {
                 for i in 0..256 {
                     self.offscreen_buffer[self.current_scanline * 256 + i] = 0;
@@ -657,26 +643,37 @@ impl Ppu {
             else {
                 for i in 0..256 {
                     self.offscreen_buffer[self.current_scanline * 256 + i] = 
-                        NES_PALETTE[self.name_tables[0x1f00] as usize];
+                        self.palette[self.name_tables[0x1f00] as usize];
                     self.sprite_0_buffer[i] = 0;                    
                 } 
             }
-            self.sprites_crossed = 0;
+            
+            // Sprites are found on the line before the one they're drawn on
+            if self.rendering_enabled() && self.current_scanline > 0 {
+                let line = self.current_scanline - 1;
+                self.evaluate_sprites(line);
+            }
+            else {
+                self.sprite_count = 0;
+            }
             
             if self.sprites_visible {
-                self.render_sprites(0x20);
+                self.render_sprites(mapper, 0x20);
             }
             
             if self.background_visible {
-                self.render_background();
+                self.render_background(mapper);
             }
             
             if self.sprites_visible {
-                self.render_sprites(0);
+                self.render_sprites(mapper, 0);
             }
             
-            if !self.sprite_0_hit {            
-                for i in 0..256 {
+            // Both layers have to be on, and a hit can't happen where
+            // either is clipped at the left edge, or at x=255
+            if !self.sprite_0_hit && self.background_visible && self.sprites_visible {
+                let first = if self.no_background_clipping && self.no_sprite_clipping {0} else {8};
+                for i in first..255 {
                     if self.sprite_0_buffer[i] > 4 {
                         self.sprite_0_hit = true;
                     }
@@ -688,23 +685,414 @@ impl Ppu {
                     self.offscreen_buffer[self.current_scanline * 256 + i] = 0;
                 }
             }
+
+            if self.rendering_enabled() {
+                self.increment_y();
+            }
+        }
+
+        // The pre-render line reloads the whole scroll position from t
+        if self.current_scanline == self.pre_render_line {
+            self.vblank = false;
+            self.sprite_0_hit = false;
+            self.sprite_overflow = false;
+            if self.rendering_enabled() {
+                self.copy_horizontal_scroll();
+                self.copy_vertical_scroll();
+            }
+        }
+
+        // The MMC3 counts the lines the PPU fetches sprite patterns on
+        if (self.current_scanline < 240 || self.current_scanline == self.pre_render_line) &&
+            (self.background_visible || self.sprites_visible) {
+            mapper.clock_scanline();
         }
 
         self.current_scanline += 1;
         
-        if self.current_scanline > 262 {
+        // Odd frames aren't shortened here, as lines are run a whole number
+        // of CPU cycles at a time
+        if self.current_scanline > self.pre_render_line {
             self.current_scanline = 0;
-            if self.fix_scroll_reset {
-                self.name_table_address = 0x2000;
-            }
-            self.sprite_0_hit = false;
         }
         
-        if (self.current_scanline == 240) && self.execute_nmi_on_vblank {
-            return true;
+        if self.current_scanline == self.vblank_line {
+            self.vblank = true;
+            self.frame_complete = true;
+            return self.execute_nmi_on_vblank;
+        }
+        
+        false
+    }
+
+    // Taken by the CPU between instructions
+    pub fn take_nmi(&mut self) -> bool {
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        pending
+    }
+
+    // Whether vblank has started since this was last asked
+    pub fn take_frame_complete(&mut self) -> bool {
+        let complete = self.frame_complete;
+        self.frame_complete = false;
+        complete
+    }
+
+    // Every fetch the dot renderer makes goes through here, so boards that
+    // count scanlines by watching A12 see it rise when it would
+    fn fetch(&mut self, mapper: &mut dyn Mapper, address: u16) -> u8 {
+        let a12 = (address & 0x1000) != 0;
+        if a12 && !self.a12_high && self.a12_low_dots >= A12_FILTER_DOTS {
+            mapper.clock_scanline();
+        }
+        if a12 != self.a12_high {
+            self.a12_high = a12;
+            self.a12_low_dots = 0;
+        }
+
+        if address >= 0x2000 {
+            self.name_tables[mapper.name_table_offset(address as usize)]
+        }
+        else {
+            mapper.ppu_read(address)
+        }
+    }
+
+    // Coarse X, into the next name table across at the end of this one
+    fn increment_x(&mut self) {
+        if (self.vram_addr & 0x001f) == 31 {
+            self.vram_addr = (self.vram_addr & !0x001f) ^ 0x0400;
         }
         else {
-            return false;
+            self.vram_addr += 1;
+        }
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_shift_low = (self.pattern_shift_low & 0xff00) | (self.next_pattern_low as u16);
+        self.pattern_shift_high = (self.pattern_shift_high & 0xff00) | (self.next_pattern_high as u16);
+        self.attribute_shift_low = (self.attribute_shift_low & 0xff00) |
+            if (self.next_attribute & 0x1) != 0 {0xff} else {0};
+        self.attribute_shift_high = (self.attribute_shift_high & 0xff00) |
+            if (self.next_attribute & 0x2) != 0 {0xff} else {0};
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_shift_low <<= 1;
+        self.pattern_shift_high <<= 1;
+        self.attribute_shift_low <<= 1;
+        self.attribute_shift_high <<= 1;
+    }
+
+    // Each tile takes 8 dots: its name table byte, attribute byte and the
+    // two planes of its pattern, 2 dots each
+    fn fetch_background(&mut self, mapper: &mut dyn Mapper) {
+        let v = self.vram_addr;
+
+        match (self.dot - 1) % 8 {
+            0 => {
+                self.load_background_shifters();
+                self.next_tile = self.fetch(mapper, 0x2000 | (v & 0x0fff));
+            },
+            2 => {
+                let attribute = self.fetch(mapper, 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+                let shift = ((v >> 4) & 0x4) | (v & 0x2);
+                self.next_attribute = (attribute >> shift) & 0x3;
+            },
+            4 => {
+                let address = self.background_address as u16 + (self.next_tile as u16) * 16 + (v >> 12);
+                self.next_pattern_low = self.fetch(mapper, address);
+            },
+            6 => {
+                let address = self.background_address as u16 + (self.next_tile as u16) * 16 + (v >> 12) + 8;
+                self.next_pattern_high = self.fetch(mapper, address);
+            },
+            7 => self.increment_x(),
+            _ => {}
+        }
+    }
+
+    // The 4 bytes of a sprite evaluate_sprites found
+    fn line_sprite(&self, slot: usize) -> [u8; 4] {
+        let bytes = if slot < 8 {
+            &self.secondary_oam[slot * 4..slot * 4 + 4]
+        }
+        else {
+            &self.extra_sprites[(slot - 8) * 4..(slot - 8) * 4 + 4]
+        };
+
+        [bytes[0], bytes[1], bytes[2], bytes[3]]
+    }
+
+    // Copies the first 8 sprites on the line after this one into secondary
+    // OAM.  The PPU then carries on looking for a ninth to set the overflow
+    // flag, but it steps through the bytes of each sprite as it steps
+    // through the sprites, so it takes tiles, attributes and X for Y and
+    // can get it wrong either way.
+    fn evaluate_sprites(&mut self, line: usize) {
+        let size = self.sprite_size;
+        let in_range = |y: u8| line.wrapping_sub(y as usize) < size;
+
+        self.secondary_oam = [0xff; 32];
+        self.extra_sprites.clear();
+        self.sprite_count = 0;
+        self.sprite_zero_on_line = false;
+
+        let mut n = 0;
+        while n < 64 && self.sprite_count < 8 {
+            if in_range(self.sprite_ram[n * 4]) {
+                let slot = self.sprite_count * 4;
+                self.secondary_oam[slot..slot + 4].copy_from_slice(&self.sprite_ram[n * 4..n * 4 + 4]);
+                self.sprite_zero_on_line |= n == 0;
+                self.sprite_count += 1;
+            }
+            n += 1;
+        }
+
+        let after_eighth = n;
+        let mut m = 0;
+        while n < 64 {
+            if in_range(self.sprite_ram[n * 4 + m]) {
+                self.sprite_overflow = true;
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0x3;
+        }
+
+        if self.no_sprite_limit {
+            for n in after_eighth..64 {
+                if in_range(self.sprite_ram[n * 4]) {
+                    self.extra_sprites.extend_from_slice(&self.sprite_ram[n * 4..n * 4 + 4]);
+                    self.sprite_count += 1;
+                }
+            }
+        }
+    }
+
+    // Where the given row of a sprite's pattern is.  8x16 sprites take
+    // their pattern table from bit 0 of the tile number.  The size is the
+    // one in $2000 now, which a game can change after the sprite was found
+    // with the other, so like the hardware only the row's low bits count.
+    fn sprite_pattern_address(&self, tile: u8, attributes: u8, row: usize) -> u16 {
+        let row = row & (self.sprite_size - 1);
+        let row = if (attributes & 0x80) != 0 { self.sprite_size - 1 - row } else { row };
+
+        if self.sprite_size == 8 {
+            (self.sprite_address + (tile as usize) * 16 + row) as u16
+        }
+        else {
+            let table = ((tile & 0x1) as usize) * 0x1000;
+            let tile = ((tile & 0xfe) as usize) + row / 8;
+            (table + tile * 16 + (row & 0x7)) as u16
+        }
+    }
+
+    fn load_sprite_pattern(&mut self, slot: usize, high_plane: bool, pattern: u8) {
+        let sprite = self.line_sprite(slot);
+        let pattern = if (sprite[2] & 0x40) != 0 { pattern.reverse_bits() } else { pattern };
+
+        if high_plane {
+            self.sprite_pattern_high[slot] = pattern;
+        }
+        else {
+            self.sprite_pattern_low[slot] = pattern;
+            self.sprite_attributes[slot] = sprite[2];
+            self.sprite_x[slot] = sprite[3];
+        }
+    }
+
+    // One sprite from secondary OAM every 8 dots, in the same pattern as
+    // the background fetches.  Empty slots still fetch, from tile $FF.
+    fn fetch_sprite(&mut self, mapper: &mut dyn Mapper) {
+        let slot = (self.dot - 257) / 8;
+
+        match (self.dot - 257) % 8 {
+            0 | 2 => {
+                let v = self.vram_addr;
+                self.fetch(mapper, 0x2000 | (v & 0x0fff));
+            },
+            4 | 6 => {
+                let high_plane = (self.dot - 257) % 8 == 6;
+                let address = if slot < self.sprite_count {
+                    let sprite = self.line_sprite(slot);
+                    self.sprite_pattern_address(sprite[1], sprite[2], self.current_scanline.wrapping_sub(sprite[0] as usize))
+                }
+                else {
+                    self.sprite_pattern_address(0xff, 0, 0)
+                };
+
+                let pattern = self.fetch(mapper, address + if high_plane {8} else {0});
+                if slot < self.sprite_count {
+                    self.load_sprite_pattern(slot, high_plane, pattern);
+                }
+            },
+            _ => {}
+        }
+
+        // Sprites past the eighth, when the limit is off.  These reads
+        // don't happen on hardware, so they're kept off the bus where a
+        // board counting A12 rises would see them.
+        if self.dot == 320 {
+            for slot in 8..self.sprite_count {
+                let sprite = self.line_sprite(slot);
+                let address = self.sprite_pattern_address(sprite[1], sprite[2], self.current_scanline.wrapping_sub(sprite[0] as usize));
+                let low = mapper.ppu_read(address);
+                let high = mapper.ppu_read(address + 8);
+                self.load_sprite_pattern(slot, false, low);
+                self.load_sprite_pattern(slot, true, high);
+            }
+        }
+    }
+
+    // Muxes the background and sprite pixels at x, then moves the sprites
+    // on a dot
+    fn draw_pixel(&mut self, x: usize) {
+        let mut background = 0;
+        let mut background_palette = 0;
+        if self.background_visible && (x >= 8 || self.no_background_clipping) {
+            let bit = 0x8000 >> self.fine_x;
+            background = (((self.pattern_shift_high & bit) != 0) as u8) << 1 |
+                ((self.pattern_shift_low & bit) != 0) as u8;
+            background_palette = (((self.attribute_shift_high & bit) != 0) as u8) << 1 |
+                ((self.attribute_shift_low & bit) != 0) as u8;
+        }
+
+        let mut sprite = 0;
+        let mut sprite_slot = 0;
+        if self.sprites_visible && (x >= 8 || self.no_sprite_clipping) {
+            for slot in 0..self.sprite_count {
+                if self.sprite_x[slot] != 0 {
+                    continue;
+                }
+                let pixel = ((self.sprite_pattern_high[slot] >> 7) << 1) | (self.sprite_pattern_low[slot] >> 7);
+                if pixel != 0 {
+                    sprite = pixel;
+                    sprite_slot = slot;
+                    break;
+                }
+            }
+        }
+
+        let attributes = self.sprite_attributes[sprite_slot];
+        let index = match (background, sprite) {
+            (0, 0) => 0,
+            (0, _) => 0x10 | ((attributes & 0x3) << 2) | sprite,
+            (_, 0) => (background_palette << 2) | background,
+            _ => {
+                // Clipping has already made both transparent at the left
+                // edge if it's on
+                if sprite_slot == 0 && self.sprite_zero_on_line && x != 255 {
+                    self.sprite_0_hit = true;
+                }
+                if (attributes & 0x20) != 0 {
+                    (background_palette << 2) | background
+                }
+                else {
+                    0x10 | ((attributes & 0x3) << 2) | sprite
+                }
+            }
+        };
+
+        let colour = self.name_tables[0x1f00 + Ppu::palette_index(index as usize)] & 0x3f;
+        self.offscreen_buffer[self.current_scanline * 256 + x] = self.palette[colour as usize];
+
+        if self.rendering_enabled() {
+            for slot in 0..self.sprite_count {
+                if self.sprite_x[slot] != 0 {
+                    self.sprite_x[slot] -= 1;
+                }
+                else {
+                    self.sprite_pattern_low[slot] <<= 1;
+                    self.sprite_pattern_high[slot] <<= 1;
+                }
+            }
+        }
+    }
+
+    // Runs the PPU for one dot.  The CPU steps it 3 dots per cycle unless
+    // fast_render is set.
+    //
+    // Each visible line draws a pixel per dot from 1 to 256 while fetching
+    // the tiles 2 ahead, fetches the next line's sprites from 257 to 320
+    // and its first two tiles from 321 to 336.  The pre-render line does
+    // the same fetches without drawing, and reloads v from t.
+    pub fn tick(&mut self, mapper: &mut dyn Mapper) {
+        let line = self.current_scanline;
+        let dot = self.dot;
+        let pre_render_line = self.pre_render_line;
+
+        if (line < 240 || line == pre_render_line) && self.rendering_enabled() {
+            if (2..257).contains(&dot) || (321..338).contains(&dot) {
+                self.shift_background();
+                self.fetch_background(mapper);
+            }
+            if dot == 256 {
+                self.increment_y();
+            }
+            if dot == 257 {
+                self.copy_horizontal_scroll();
+                if line < 240 {
+                    self.evaluate_sprites(line);
+                }
+                else {
+                    self.sprite_count = 0;
+                }
+            }
+            if (257..321).contains(&dot) {
+                self.fetch_sprite(mapper);
+            }
+            if dot == 338 || dot == 340 {
+                let v = self.vram_addr;
+                self.fetch(mapper, 0x2000 | (v & 0x0fff));
+            }
+            if line == pre_render_line && (280..305).contains(&dot) {
+                self.copy_vertical_scroll();
+            }
+        }
+
+        if line < 240 && (1..257).contains(&dot) {
+            self.draw_pixel(dot - 1);
+        }
+
+        if line == pre_render_line && dot == 1 {
+            self.vblank = false;
+            self.vblank_suppressed = false;
+            self.sprite_0_hit = false;
+            self.sprite_overflow = false;
+        }
+
+        if line == self.vblank_line && dot == 1 {
+            self.frame_complete = true;
+        }
+
+        if line == self.vblank_line && dot == 1 && !self.vblank_suppressed {
+            self.vblank = true;
+            if self.execute_nmi_on_vblank {
+                self.nmi_pending = true;
+            }
+        }
+
+        if !self.a12_high {
+            self.a12_low_dots = self.a12_low_dots.saturating_add(1);
+        }
+
+        self.dot += 1;
+        // Odd frames skip the pre-render line's last dot while rendering
+        if line == pre_render_line && self.dot == DOTS_PER_LINE - 1 &&
+            self.odd_frame && self.short_odd_frames && self.rendering_enabled() {
+            self.dot = DOTS_PER_LINE;
+        }
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.current_scanline += 1;
+            self.scanline_ended = true;
+
+            if self.current_scanline > pre_render_line {
+                self.current_scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
         }
     }
 } 
\ No newline at end of file
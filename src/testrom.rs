@@ -0,0 +1,53 @@
+use nes::Nes;
+use mmu::Mmu;
+
+// Reads the zero terminated text a test ROM leaves at $6004
+fn result_text(mmu: &Mmu) -> String {
+    let mut text = String::new();
+    let mut address = 0x6004;
+
+    while address < 0x8000 && mmu.peek_u8(address) != 0 {
+        text.push(mmu.peek_u8(address) as char);
+        address += 1;
+    }
+
+    String::from(text.trim())
+}
+
+// Runs a ROM that reports through $6000 the way blargg's test ROMs do:
+// $DE $B0 $61 at $6001-$6003 marks the report as valid, $6000 reads $80
+// while the test runs, $81 when it wants a reset and otherwise holds the
+// result code.  Returns the ROM's text, as the error if the code isn't 0.
+pub fn run(nes: &mut Nes, max_frames: usize) -> Result<String, String> {
+    // Time to wait before pressing reset, as the ROMs ask
+    const RESET_DELAY_FRAMES : usize = 6;
+
+    let mut reset_frame = None;
+
+    for frame in 0..max_frames {
+        if let Err(e) = nes.step_frame() {
+            return Err(format!("{} ({})", e, result_text(&nes.mmu)));
+        }
+
+        let signature = [nes.mmu.peek_u8(0x6001), nes.mmu.peek_u8(0x6002), nes.mmu.peek_u8(0x6003)];
+        if signature != [0xde, 0xb0, 0x61] {
+            continue;
+        }
+
+        match nes.mmu.peek_u8(0x6000) {
+            0x80 => {},
+            0x81 => match reset_frame {
+                None => reset_frame = Some(frame + RESET_DELAY_FRAMES),
+                Some(f) if f == frame => {
+                    nes.reset();
+                    reset_frame = None;
+                },
+                _ => {}
+            },
+            0 => return Ok(result_text(&nes.mmu)),
+            code => return Err(format!("result {}: {}", code, result_text(&nes.mmu)))
+        }
+    }
+
+    Err(format!("timed out after {} frames ({})", max_frames, result_text(&nes.mmu)))
+}
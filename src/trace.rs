@@ -0,0 +1,108 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::fs::File;
+
+use cpu::Cpu;
+use mmu::Mmu;
+use opcodes::{OPCODES, Instr, Mode};
+
+// Writes one line per instruction in the format of nestest.log, so runs can
+// be diffed against reference logs from other emulators
+pub struct Tracer {
+    out: BufWriter<File>
+}
+
+impl Tracer {
+    pub fn new(fname: &str) -> Result<Tracer, io::Error> {
+        let f = try!(File::create(fname));
+
+        Ok(Tracer { out: BufWriter::new(f) })
+    }
+
+    // Logs the instruction at pc, before it executes
+    pub fn log(&mut self, cpu: &Cpu, mmu: &Mmu) -> Result<(), io::Error> {
+        writeln!(self.out, "{}", trace_line(cpu, mmu))
+    }
+}
+
+fn peek_u16(mmu: &Mmu, address: u16) -> u16 {
+    ((mmu.peek_u8(address.wrapping_add(1)) as u16) << 8) + (mmu.peek_u8(address) as u16)
+}
+
+fn peek_zero_page_u16(mmu: &Mmu, address: u8) -> u16 {
+    ((mmu.peek_u8(address.wrapping_add(1) as u16) as u16) << 8) + (mmu.peek_u8(address as u16) as u16)
+}
+
+// Operands are shown with the memory they touch, as it was before the
+// instruction ran.  Eg: "LDA ($89),Y = 0300 @ 0300 = 89"
+fn operand_text(cpu: &Cpu, mmu: &Mmu) -> String {
+    let opcode = &OPCODES[mmu.peek_u8(cpu.pc) as usize];
+    let arg1 = mmu.peek_u8(cpu.pc.wrapping_add(1));
+    let addr = peek_u16(mmu, cpu.pc.wrapping_add(1));
+
+    match opcode.mode {
+        Mode::Implied => String::new(),
+        Mode::Accumulator => String::from("A"),
+        Mode::Immediate => format!("#${:02X}", arg1),
+        Mode::ZeroPage => format!("${:02X} = {:02X}", arg1, mmu.peek_u8(arg1 as u16)),
+        Mode::ZeroPageX => {
+            let target = arg1.wrapping_add(cpu.x);
+            format!("${:02X},X @ {:02X} = {:02X}", arg1, target, mmu.peek_u8(target as u16))
+        },
+        Mode::ZeroPageY => {
+            let target = arg1.wrapping_add(cpu.y);
+            format!("${:02X},Y @ {:02X} = {:02X}", arg1, target, mmu.peek_u8(target as u16))
+        },
+        Mode::Absolute => match opcode.instr {
+            Instr::Jmp | Instr::Jsr => format!("${:04X}", addr),
+            _ => format!("${:04X} = {:02X}", addr, mmu.peek_u8(addr))
+        },
+        Mode::AbsoluteX => {
+            let target = addr.wrapping_add(cpu.x as u16);
+            format!("${:04X},X @ {:04X} = {:02X}", addr, target, mmu.peek_u8(target))
+        },
+        Mode::AbsoluteY => {
+            let target = addr.wrapping_add(cpu.y as u16);
+            format!("${:04X},Y @ {:04X} = {:02X}", addr, target, mmu.peek_u8(target))
+        },
+        Mode::Indirect => {
+            let target = ((mmu.peek_u8((addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff)) as u16) << 8) +
+                (mmu.peek_u8(addr) as u16);
+            format!("(${:04X}) = {:04X}", addr, target)
+        },
+        Mode::IndirectX => {
+            let ptr = arg1.wrapping_add(cpu.x);
+            let target = peek_zero_page_u16(mmu, ptr);
+            format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", arg1, ptr, target, mmu.peek_u8(target))
+        },
+        Mode::IndirectY => {
+            let base = peek_zero_page_u16(mmu, arg1);
+            let target = base.wrapping_add(cpu.y as u16);
+            format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", arg1, base, target, mmu.peek_u8(target))
+        },
+        Mode::Relative => format!("${:04X}", cpu.pc.wrapping_add(2).wrapping_add(arg1 as i8 as u16))
+    }
+}
+
+pub fn trace_line(cpu: &Cpu, mmu: &Mmu) -> String {
+    let opcode = &OPCODES[mmu.peek_u8(cpu.pc) as usize];
+
+    let bytes : Vec<String> = (0..opcode.mode.length())
+        .map(|i| format!("{:02X}", mmu.peek_u8(cpu.pc.wrapping_add(i))))
+        .collect();
+
+    // nestest.log knows isc by its other name
+    let name = match opcode.instr {
+        Instr::Isc => String::from("ISB"),
+        _ => opcode.name.to_uppercase()
+    };
+    let operand = operand_text(cpu, mmu);
+    let text = if operand.is_empty() { name } else { format!("{} {}", name, operand) };
+
+    format!("{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        cpu.pc, bytes.join(" "), if opcode.unofficial { "*" } else { " " }, text,
+        cpu.a, cpu.x, cpu.y, cpu.status(), cpu.sp,
+        mmu.ppu.current_scanline, if mmu.ppu.fast_render { cpu.tick_count * 3 } else { mmu.ppu.dot as u32 },
+        cpu.cycle_count)
+}
@@ -1,6 +1,5 @@
 use std::io;
 use std::io::prelude::*;
-use std::fs::File;
 
 pub trait BitReader {
     fn read_u16_be(&mut self) -> Result<u16, io::Error>;
@@ -12,7 +11,7 @@ pub trait BitReader {
     fn read_u8(&mut self) -> Result<u8, io::Error>;
 }
 
-impl BitReader for File {
+impl<R: Read> BitReader for R {
     fn read_u32_be(&mut self) -> Result<u32, io::Error> {
         let mut buffer = [0; 4];
     
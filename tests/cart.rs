@@ -0,0 +1,331 @@
+extern crate rustynes;
+extern crate flate2;
+extern crate zip;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use zip::ZipWriter;
+use zip::write::FileOptions;
+
+use rustynes::Nes;
+use rustynes::mmu::Mmu;
+use rustynes::cart::{CartHeader, LoadError, region, console, load_cart, load_rom_with_games, read_rom_file, save_file_name};
+use rustynes::ppu::mirroring;
+use rustynes::gamedb;
+use rustynes::gamedb::{Game, Key};
+
+fn header(bytes: &[u8]) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..bytes.len()].copy_from_slice(bytes);
+    header
+}
+
+#[test]
+fn ines_header() {
+    let header = CartHeader::parse(&header(&[0x4e, 0x45, 0x53, 0x1a, 8, 16, 0x43, 0x10])).unwrap();
+
+    assert!(!header.nes2);
+    assert_eq!(header.mapper, 0x14);
+    assert_eq!(header.prg_rom_size, 0x20000);
+    assert_eq!(header.chr_rom_size, 0x20000);
+    assert_eq!(header.mirroring, mirroring::VERTICAL);
+    assert!(header.battery);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.region, region::NTSC);
+}
+
+#[test]
+fn dirty_ines_header_ignores_the_top_of_the_mapper() {
+    let mut bytes = header(&[0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x10]);
+    bytes[7..16].copy_from_slice(b"DiskDude!");
+    let header = CartHeader::parse(&bytes).unwrap();
+
+    assert_eq!(header.mapper, 1);
+    assert_eq!(header.console, console::NES);
+
+    // A stray bit 2 in byte 7 means the rest of it is junk too
+    let bytes = [0x4e, 0x45, 0x53, 0x1a, 8, 0, 0x23, 0x64, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(CartHeader::parse(&bytes).unwrap().mapper, 2);
+}
+
+#[test]
+fn nes2_header() {
+    let header = CartHeader::parse(&header(&[
+        0x4e, 0x45, 0x53, 0x1a, 0x02, 0x00, 0x12, 0x49,
+        0x31, 0x10, 0x70, 0x07, 0x01, 0x21, 0x01, 0x05])).unwrap();
+
+    assert!(header.nes2);
+    assert_eq!(header.mapper, 0x141);
+    assert_eq!(header.submapper, 3);
+    assert_eq!(header.prg_rom_size, 0x8000);
+    assert_eq!(header.chr_rom_size, 0x200000);
+    assert_eq!(header.prg_ram_size, 0);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.chr_ram_size, 0x2000);
+    assert_eq!(header.region, region::PAL);
+    assert_eq!(header.console, console::VS_SYSTEM);
+    assert_eq!(header.vs_ppu_type, 1);
+    assert_eq!(header.vs_hardware_type, 2);
+    assert_eq!(header.misc_roms, 1);
+    assert_eq!(header.expansion_device, 5);
+}
+
+#[test]
+fn nes2_exponent_sizes() {
+    // 2^3 * 3 bytes of PRG, 2^10 * 1 of CHR
+    let sizes = CartHeader::parse(&header(&[
+        0x4e, 0x45, 0x53, 0x1a, 0x0d, 0x28, 0x00, 0x08, 0x00, 0xff])).unwrap();
+
+    assert_eq!(sizes.prg_rom_size, 24);
+    assert_eq!(sizes.chr_rom_size, 1024);
+
+    // 2^63 * 7 doesn't fit
+    let error = CartHeader::parse(&header(&[
+        0x4e, 0x45, 0x53, 0x1a, 0xff, 0x00, 0x00, 0x08, 0x00, 0x0f])).unwrap_err();
+    assert_eq!(error.to_string(), "Bad header: PRG ROM size is too large");
+
+    // 2^63 each does, but not both together
+    let mut nes = Nes::new();
+    match nes.load_rom(&header(&[0x4e, 0x45, 0x53, 0x1a, 0xfc, 0xfc, 0x00, 0x08, 0x00, 0xff])) {
+        Err(LoadError::BadHeader(_)) => {},
+        other => panic!("{:?}", other)
+    }
+}
+
+#[test]
+fn trainer_is_loaded_at_7000() {
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.extend(vec![0xaa; 512]);
+    image.extend(vec![0x55; 0x4000]);
+
+    let mut nes = Nes::new();
+    nes.load_rom(&image).unwrap();
+
+    assert_eq!(nes.mmu.peek_u8(0x6fff), 0);
+    assert_eq!(nes.mmu.peek_u8(0x7000), 0xaa);
+    assert_eq!(nes.mmu.peek_u8(0x71ff), 0xaa);
+    assert_eq!(nes.mmu.peek_u8(0x7200), 0);
+    assert_eq!(nes.mmu.peek_u8(0x8000), 0x55);
+    assert!(nes.mmu.mapper.cart().header.to_string().contains("Trainer: 512 bytes at $7000-$71FF"));
+}
+
+#[test]
+fn load_errors_say_what_is_wrong() {
+    let mut nes = Nes::new();
+
+    match nes.load_rom(b"MZ\x90\x00 not a rom at all") {
+        Err(LoadError::BadMagic) => {},
+        other => panic!("{:?}", other)
+    }
+
+    match nes.load_rom(&[0x4e, 0x45, 0x53, 0x1a, 1]) {
+        Err(LoadError::Truncated { expected: 16, got: 5 }) => {},
+        other => panic!("{:?}", other)
+    }
+
+    // 16k of PRG and 8k of CHR, with the CHR cut short
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.resize(16 + 0x4000 + 0x1000, 0);
+    let error = nes.load_rom(&image).unwrap_err();
+    assert_eq!(error.to_string(), "File is cut short: expected 24592 bytes, got 20496");
+
+    let mut image = unif_image(b"NES-NROM-256\0");
+    image.truncate(image.len() - 1);
+    match nes.load_rom(&image) {
+        Err(LoadError::Truncated { expected, got }) => assert_eq!(expected, got + 1),
+        other => panic!("{:?}", other)
+    }
+}
+
+fn unif_chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    let length = data.len() as u32;
+    chunk.extend(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+    chunk.extend(data);
+    chunk
+}
+
+fn unif_image(board: &[u8]) -> Vec<u8> {
+    let mut image = b"UNIF".to_vec();
+    image.extend(&[7, 0, 0, 0]);
+    image.resize(32, 0);
+    image.extend(unif_chunk(b"MAPR", board));
+    image.extend(unif_chunk(b"NAME", b"Test Cart\0"));
+    image.extend(unif_chunk(b"MIRR", &[1]));
+    image.extend(unif_chunk(b"BATR", &[0]));
+    // Chunks are put together by number, not by the order they come in
+    image.extend(unif_chunk(b"PRG1", &[0x22; 0x4000]));
+    image.extend(unif_chunk(b"PRG0", &[0x11; 0x4000]));
+    image
+}
+
+#[test]
+fn unif_images_are_loaded() {
+    let mut nes = Nes::new();
+    nes.load_rom(&unif_image(b"NES-UNROM\0")).unwrap();
+
+    {
+        let header = &nes.mmu.mapper.cart().header;
+        assert_eq!(header.mapper, 2);
+        assert_eq!(header.board, Some(String::from("NES-UNROM")));
+        assert_eq!(header.name, Some(String::from("Test Cart")));
+        assert_eq!(header.mirroring, mirroring::VERTICAL);
+        assert!(header.battery);
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_ram_size, 0x2000);
+        assert!(header.to_string().contains("Format: UNIF (board NES-UNROM)"));
+    }
+
+    assert_eq!(nes.mmu.peek_u8(0x8000), 0x11);
+    assert_eq!(nes.mmu.peek_u8(0xc000), 0x22);
+
+    let error = nes.load_rom(&unif_image(b"UNL-MYSTERY\0")).unwrap_err();
+    assert_eq!(error.to_string(), "Unsupported board: UNL-MYSTERY");
+}
+
+#[test]
+fn hashes_cover_prg_then_chr() {
+    assert_eq!(gamedb::crc32(b"1234", b"56789"), 0xcbf43926);
+    assert_eq!(gamedb::sha1(b"a", b"bc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn database_fixes_headers() {
+    let prg = vec![0xea; 0x4000];
+    let chr = vec![0; 0x2000];
+    let games = [
+        Game { key: Key::Crc32(gamedb::crc32(&prg, &[])), mapper: Some(2), battery: Some(true), ..gamedb::BLANK },
+        Game { key: Key::Sha1("F6AAD99F69D01B84624112C146D2C2BE966747E5"), mirroring: Some(mirroring::VERTICAL), ..gamedb::BLANK }
+    ];
+
+    let game = gamedb::find_in(&games, &prg, &[]).unwrap();
+    let mut header = CartHeader::new();
+    game.apply(&mut header);
+    assert_eq!(header.mapper, 2);
+    assert!(header.battery);
+
+    // SHA-1s match whatever their case
+    let game = gamedb::find_in(&games, &prg, &chr).unwrap();
+    assert_eq!(game.mirroring, Some(mirroring::VERTICAL));
+
+    assert!(gamedb::find_in(&games, &[0; 0x4000], &[]).is_none());
+}
+
+#[test]
+fn database_entries_correct_the_loaded_header() {
+    // Claims NROM with horizontal mirroring and no battery
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.extend(vec![0xea; 0x4000]);
+    let games = [
+        Game { key: Key::Crc32(gamedb::crc32(&image[16..], &[])), name: "Bad dump",
+            mapper: Some(2), mirroring: Some(mirroring::VERTICAL), battery: Some(true), quirks: 0x80, ..gamedb::BLANK }
+    ];
+
+    let mut mmu = Mmu::new();
+    load_rom_with_games(&mut &image[..], &mut mmu, &games).unwrap();
+    let header = &mmu.mapper.cart().header;
+    assert_eq!(header.mapper, 2);
+    assert_eq!(header.mirroring, mirroring::VERTICAL);
+    assert!(header.battery);
+    assert_eq!(header.quirks, 0x80);
+
+    // Without the entry it loads as it says
+    let mut mmu = Mmu::new();
+    load_rom_with_games(&mut &image[..], &mut mmu, &[]).unwrap();
+    assert_eq!(mmu.mapper.cart().header.mirroring, mirroring::HORIZONTAL);
+
+    for game in gamedb::GAMES {
+        if let Key::Sha1(sha) = game.key {
+            assert!(sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit()), "{}", game.name);
+        }
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let mut path = env::temp_dir();
+    path.push(format!("rustynes-{}", name));
+    path
+}
+
+fn write_zip(path: &PathBuf, entries: &[(&str, &[u8])]) {
+    let mut zip = ZipWriter::new(File::create(path).unwrap());
+    for &(name, data) in entries {
+        zip.start_file(name, FileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+fn roms_are_read_from_zips() {
+    let path = temp_path("set.zip");
+    write_zip(&path, &[("readme.txt", b"hello"), ("games/a.nes", b"first"), ("b.nes", b"second")]);
+    let fname = path.to_str().unwrap();
+
+    let (rom, rom_name) = read_rom_file(fname).unwrap();
+    assert_eq!(rom, b"first");
+    assert_eq!(save_file_name(&rom_name), temp_path("set.zip").with_file_name("a.sav").to_str().unwrap());
+
+    let (rom, rom_name) = read_rom_file(&format!("{}:b.nes", fname)).unwrap();
+    assert_eq!(rom, b"second");
+    assert!(rom_name.ends_with("b.nes"));
+
+    assert!(read_rom_file(&format!("{}:c.nes", fname)).is_err());
+
+    // Lowercasing \u{130} would make it longer
+    let path = temp_path("\u{130}.ZIP");
+    write_zip(&path, &[("a.nes", b"first")]);
+    let (rom, _) = read_rom_file(&format!("{}:a.nes", path.to_str().unwrap())).unwrap();
+    assert_eq!(rom, b"first");
+}
+
+#[test]
+fn roms_are_read_from_gzip() {
+    let path = temp_path("game.nes.gz");
+    let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    gz.write_all(b"rom").unwrap();
+    gz.finish().unwrap();
+
+    let (rom, rom_name) = read_rom_file(path.to_str().unwrap()).unwrap();
+    assert_eq!(rom, b"rom");
+    assert_eq!(save_file_name(&rom_name), temp_path("game.sav").to_str().unwrap());
+}
+
+#[test]
+fn save_files_replace_rom_extensions() {
+    assert_eq!(save_file_name("roms/smb.nes"), "roms/smb.sav");
+    assert_eq!(save_file_name("smb.unf"), "smb.sav");
+    assert_eq!(save_file_name("smb.bin"), "smb.bin.sav");
+    assert_eq!(save_file_name("smb"), "smb.sav");
+}
+
+#[test]
+fn saves_fill_all_of_prg_ram() {
+    // NES 2.0 with 16k of battery backed PRG RAM
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0x02, 0x08, 0, 0, 0x80, 0, 0, 0, 0, 0];
+    image.resize(16 + 0x4000, 0);
+    let rom_path = temp_path("battery.nes");
+    File::create(&rom_path).unwrap().write_all(&image).unwrap();
+    let rom_name = String::from(rom_path.to_str().unwrap());
+
+    let mut save = vec![0; 0x4000];
+    save[0x3fff] = 0x42;
+    File::create(save_file_name(&rom_name)).unwrap().write_all(&save).unwrap();
+
+    let mut mmu = Mmu::new();
+    load_cart(&rom_name, None, None, &mut mmu).unwrap();
+    assert_eq!(mmu.mapper.cart().prg_ram[0x3fff], 0x42);
+
+    // One from before the RAM grew still loads, into the start of it
+    save[0] = 0x24;
+    File::create(save_file_name(&rom_name)).unwrap().write_all(&save[..0x2000]).unwrap();
+    let mut mmu = Mmu::new();
+    load_cart(&rom_name, None, None, &mut mmu).unwrap();
+    assert_eq!(mmu.mapper.cart().prg_ram[0], 0x24);
+    assert_eq!(mmu.mapper.cart().prg_ram[0x3fff], 0);
+}
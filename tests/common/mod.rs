@@ -0,0 +1,127 @@
+// Shared helpers for running ROMs headlessly in the integration tests
+
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+
+use rustynes::Nes;
+use rustynes::testrom;
+
+// Wraps 16k of PRG in an NROM iNES image with 8k of CHR RAM
+pub fn ines_image(prg: &[u8]) -> Vec<u8> {
+    assert!(prg.len() <= 0x4000, "PRG doesn't fit in 16k");
+
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.extend(prg.iter().cloned());
+    image.resize(16 + 0x4000, 0);
+
+    image
+}
+
+pub fn boot(image: &[u8]) -> Nes {
+    let mut nes = Nes::new();
+    nes.load_rom(image).unwrap();
+    nes
+}
+
+// Runs a ROM that reports through $6000 the way blargg's test ROMs do,
+// see testrom::run
+pub fn run_blargg_rom(image: &[u8], max_frames: usize) -> Result<String, String> {
+    testrom::run(&mut boot(image), max_frames)
+}
+
+// Runs every .nes in a directory with run_blargg_rom.  Returns a line for
+// each that fails.
+pub fn run_blargg_roms_in(dir: &str, max_frames: usize) -> Vec<String> {
+    let mut failures = Vec::new();
+    for entry in fs::read_dir(dir).unwrap_or_else(|e| panic!("Could not read {}: {}", dir, e)) {
+        let path = entry.unwrap().path();
+        if path.extension() != Some(OsStr::new("nes")) {
+            continue;
+        }
+
+        let image = fs::read(&path).unwrap();
+        if let Err(e) = run_blargg_rom(&image, max_frames) {
+            failures.push(format!("{}: {}", path.display(), e));
+        }
+    }
+
+    failures
+}
+
+const ORIGIN : u16 = 0xc000;
+
+// Builds small self-checking test ROMs that report in blargg's format.
+// Code is emitted as raw bytes from $C000, and each expect() adds a check
+// that fails the ROM with its message unless the given branch is taken.
+pub struct TestRom {
+    code: Vec<u8>,
+    checks: Vec<(usize, String)>
+}
+
+impl TestRom {
+    pub fn new() -> TestRom {
+        let mut rom = TestRom { code: Vec::new(), checks: Vec::new() };
+
+        rom.emit(&[0x78, 0xd8, 0xa2, 0xff, 0x9a]);  // sei; cld; ldx #$ff; txs
+        rom.emit(&[0xa9, 0xde, 0x8d, 0x01, 0x60]);  // lda #$de; sta $6001
+        rom.emit(&[0xa9, 0xb0, 0x8d, 0x02, 0x60]);  // lda #$b0; sta $6002
+        rom.emit(&[0xa9, 0x61, 0x8d, 0x03, 0x60]);  // lda #$61; sta $6003
+        rom.emit(&[0xa9, 0x80, 0x8d, 0x00, 0x60]);  // lda #$80; sta $6000
+
+        rom
+    }
+
+    pub fn here(&self) -> u16 {
+        ORIGIN + self.code.len() as u16
+    }
+
+    pub fn emit(&mut self, bytes: &[u8]) -> &mut TestRom {
+        self.code.extend(bytes.iter().cloned());
+        self
+    }
+
+    // Emits `branch +3; jmp <failure>`, the failure being patched in by build()
+    pub fn expect(&mut self, branch: u8, message: &str) -> &mut TestRom {
+        self.emit(&[branch, 0x03, 0x4c]);
+        self.checks.push((self.code.len(), String::from(message)));
+        self.emit(&[0x00, 0x00])
+    }
+
+    // Writes text to $6004 and code to $6000, then hangs
+    fn emit_report(&mut self, code: u8, text: &str) {
+        let text_address = self.here() + 21;
+
+        self.emit(&[0xa2, 0x00]);                                       // ldx #0
+        self.emit(&[0xbd, text_address as u8, (text_address >> 8) as u8]);  // lda text,x
+        self.emit(&[0x9d, 0x04, 0x60, 0xf0, 0x03, 0xe8, 0xd0, 0xf5]);  // sta $6004,x; beq +3; inx; bne -11
+        self.emit(&[0xa9, code, 0x8d, 0x00, 0x60]);                     // lda #code; sta $6000
+        let hang = self.here();
+        self.emit(&[0x4c, hang as u8, (hang >> 8) as u8]);              // jmp *
+        self.emit(text.as_bytes());
+        self.emit(&[0]);
+    }
+
+    pub fn build(&mut self) -> Vec<u8> {
+        self.emit_report(0, "Passed");
+
+        let checks = self.checks.clone();
+        for (i, &(offset, ref message)) in checks.iter().enumerate() {
+            let failure = self.here();
+            self.code[offset] = failure as u8;
+            self.code[offset + 1] = (failure >> 8) as u8;
+            self.emit_report((i + 1) as u8, message);
+        }
+
+        // Every vector points at the start of the ROM
+        let mut prg = self.code.clone();
+        prg.resize(0x4000, 0);
+        for vector in 0..3 {
+            prg[0x3ffa + vector * 2] = ORIGIN as u8;
+            prg[0x3ffb + vector * 2] = (ORIGIN >> 8) as u8;
+        }
+
+        ines_image(&prg)
+    }
+}
@@ -0,0 +1,49 @@
+extern crate rustynes;
+
+use rustynes::cart::LoadError;
+use rustynes::gamedb;
+use rustynes::info::{RomInfo, error_json};
+
+fn image() -> Vec<u8> {
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x13, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.resize(16 + 0x8000, 0x11);
+    image.resize(16 + 0x8000 + 0x2000, 0x22);
+    image
+}
+
+#[test]
+fn info_describes_the_rom() {
+    let info = RomInfo::from_image("game \"1\".nes", &image()).unwrap();
+    let crc = gamedb::crc32(&[0x11; 0x8000], &[0x22; 0x2000]);
+
+    assert_eq!(info.header.mapper, 1);
+    assert_eq!(info.crc32, crc);
+    assert!(info.game.is_none());
+
+    let text = info.to_string();
+    assert!(text.contains("Format: iNES"), "{}", text);
+    assert!(text.contains("Mapper: 1 (MMC1)"), "{}", text);
+    assert!(text.contains("Mirroring: vertical"), "{}", text);
+    assert!(text.contains(&format!("CRC32: {:08x}", crc)), "{}", text);
+    assert!(text.ends_with("Database: no match"), "{}", text);
+
+    let json = info.to_json();
+    assert!(json.starts_with("{\"file\":\"game \\\"1\\\".nes\",\"format\":\"iNES\",\"board\":null,"), "{}", json);
+    assert!(json.contains("\"mapper\":1,\"mapper_name\":\"MMC1\","), "{}", json);
+    assert!(json.contains("\"prg_rom_size\":32768,\"chr_rom_size\":8192,"), "{}", json);
+    assert!(json.contains("\"battery\":true,\"trainer\":false,\"region\":\"NTSC\","), "{}", json);
+    assert!(json.contains(&format!("\"crc32\":\"{:08x}\"", crc)), "{}", json);
+    assert!(json.ends_with("\"database\":null}"), "{}", json);
+}
+
+#[test]
+fn errors_are_reported_as_json_too() {
+    let error = RomInfo::from_image("short.nes", &image()[..100]).err().unwrap();
+    match error {
+        LoadError::Truncated { expected: 40976, got: 100 } => {},
+        ref other => panic!("{:?}", other)
+    }
+
+    assert_eq!(error_json("short.nes", &error),
+        "{\"file\":\"short.nes\",\"error\":\"File is cut short: expected 40976 bytes, got 100\"}");
+}
@@ -0,0 +1,121 @@
+extern crate rustynes;
+
+use rustynes::Nes;
+
+// An iNES image with every 16k of PRG filled with its bank number, and
+// every 1k of CHR likewise
+fn image(mapper: u8, prg_banks: usize, chr_banks: usize) -> Vec<u8> {
+    let mut image = vec![0x4e, 0x45, 0x53, 0x1a, prg_banks as u8, chr_banks as u8,
+        (mapper & 0xf) << 4, mapper & 0xf0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    for bank in 0..prg_banks {
+        image.extend(vec![bank as u8; 0x4000]);
+    }
+    for page in 0..(chr_banks * 8) {
+        image.extend(vec![page as u8; 0x400]);
+    }
+
+    image
+}
+
+fn boot(image: &[u8]) -> Nes {
+    let mut nes = Nes::new();
+    nes.load_rom(image).unwrap();
+    nes
+}
+
+#[test]
+fn uxrom_switches_the_bank_at_8000() {
+    let mut nes = boot(&image(2, 4, 0));
+    assert_eq!(nes.mmu.peek_u8(0x8000), 0);
+    assert_eq!(nes.mmu.peek_u8(0xc000), 3);
+
+    nes.mmu.write_u8(0x8000, 2);
+    assert_eq!(nes.mmu.peek_u8(0x8000), 2);
+    assert_eq!(nes.mmu.peek_u8(0xc000), 3);
+}
+
+#[test]
+fn mmc1_loads_registers_serially() {
+    let mut nes = boot(&image(1, 4, 2));
+
+    // Five writes of bit 0, low bit first, select 16k bank 2 at $8000
+    for bit in 0..5 {
+        nes.mmu.write_u8(0xe000, (2 >> bit) & 1);
+    }
+    assert_eq!(nes.mmu.peek_u8(0x8000), 2);
+    assert_eq!(nes.mmu.peek_u8(0xc000), 3);
+}
+
+#[test]
+fn mmc3_counts_scanlines_into_an_irq() {
+    let mut nes = boot(&image(4, 2, 1));
+
+    nes.mmu.write_u8(0xc000, 2);
+    nes.mmu.write_u8(0xc001, 0);
+    nes.mmu.write_u8(0xe001, 0);
+
+    for _ in 0..2 {
+        nes.mmu.mapper.clock_scanline();
+        assert!(!nes.mmu.irq_pending());
+    }
+    nes.mmu.mapper.clock_scanline();
+    assert!(nes.mmu.irq_pending());
+
+    nes.mmu.write_u8(0xe000, 0);
+    assert!(!nes.mmu.irq_pending());
+}
+
+#[test]
+fn mmc3_is_clocked_by_the_sprite_fetches() {
+    let mut nes = boot(&image(4, 2, 1));
+
+    // Sprites from $1000, so A12 rises once a line as they're fetched
+    nes.mmu.write_u8(0x2000, 0x08);
+    nes.mmu.write_u8(0x2001, 0x18);
+    nes.mmu.write_u8(0xc000, 5);
+    nes.mmu.write_u8(0xc001, 0);
+    nes.mmu.write_u8(0xe001, 0);
+
+    // The pre-render line loads the counter, lines 0 to 4 count it down
+    let run_to = |nes: &mut Nes, line: usize, dot: usize| {
+        while nes.mmu.ppu.current_scanline != line || nes.mmu.ppu.dot != dot {
+            nes.mmu.ppu.tick(&mut *nes.mmu.mapper);
+        }
+    };
+    nes.mmu.ppu.current_scanline = 261;
+    run_to(&mut nes, 4, 256);
+    assert!(!nes.mmu.irq_pending());
+    run_to(&mut nes, 4, 270);
+    assert!(nes.mmu.irq_pending());
+}
+
+#[test]
+fn save_state_restores_the_banks() {
+    let mut nes = boot(&image(4, 4, 2));
+
+    // R6 = 8k bank 3, R2 = 1k CHR page 9
+    nes.mmu.write_u8(0x8000, 6);
+    nes.mmu.write_u8(0x8001, 3);
+    nes.mmu.write_u8(0x8000, 2);
+    nes.mmu.write_u8(0x8001, 9);
+    nes.mmu.write_u8(0x6000, 0x55);
+    let state = nes.mmu.mapper.save_state();
+
+    nes.mmu.write_u8(0x8000, 6);
+    nes.mmu.write_u8(0x8001, 0);
+    nes.mmu.write_u8(0x6000, 0);
+
+    nes.mmu.mapper.load_state(&state).unwrap();
+    assert_eq!(nes.mmu.peek_u8(0x8000), 1);
+    assert_eq!(nes.mmu.mapper.ppu_read(0x1000), 9);
+    assert_eq!(nes.mmu.peek_u8(0x6000), 0x55);
+}
+
+#[test]
+fn unknown_mappers_are_rejected() {
+    let mut nes = Nes::new();
+    let err = nes.load_rom(&image(5, 2, 1)).unwrap_err();
+
+    assert_eq!(err.to_string(), "Unsupported mapper: 5");
+}
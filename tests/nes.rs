@@ -0,0 +1,263 @@
+// Drives the headless Nes core the way a frontend would
+
+extern crate rustynes;
+
+mod common;
+
+use rustynes::Nes;
+use rustynes::cart::region;
+use rustynes::joypad::button;
+use rustynes::movie::Movie;
+use rustynes::ppu::parse_palette;
+use rustynes::nes::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::time::Instant;
+
+use common::{boot, ines_image, TestRom};
+
+// Forever strobes the controllers and shifts their buttons into $10 and $11,
+// copying each complete read to $12 and $13
+fn joypad_rom() -> Vec<u8> {
+    let mut rom = TestRom::new();
+
+    let start = rom.here();
+    rom.emit(&[0xa9, 0x01, 0x8d, 0x16, 0x40])  // lda #$01; sta $4016
+        .emit(&[0xa9, 0x00, 0x8d, 0x16, 0x40])  // lda #$00; sta $4016
+        .emit(&[0xa2, 0x08])                    // ldx #$08
+        .emit(&[0xad, 0x16, 0x40, 0x4a, 0x26, 0x10])  // lda $4016; lsr a; rol $10
+        .emit(&[0xad, 0x17, 0x40, 0x4a, 0x26, 0x11])  // lda $4017; lsr a; rol $11
+        .emit(&[0xca, 0xd0, 0xf1])              // dex; bne -15
+        .emit(&[0xa5, 0x10, 0x85, 0x12])        // lda $10; sta $12
+        .emit(&[0xa5, 0x11, 0x85, 0x13])        // lda $11; sta $13
+        .emit(&[0x4c, start as u8, (start >> 8) as u8]);  // jmp start
+
+    rom.build()
+}
+
+#[test]
+fn step_frame_produces_video_and_audio() {
+    let mut nes = boot(&joypad_rom());
+
+    nes.step_frame().unwrap();
+    nes.step_frame().unwrap();
+
+    assert_eq!(nes.frame_count, 2);
+    assert!(nes.is_frame_complete());
+    assert_eq!(nes.framebuffer().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    assert!(!nes.audio_samples().is_empty());
+    assert_eq!(nes.audio_samples().len(), 0);
+}
+
+#[test]
+fn audio_is_capped_if_never_taken() {
+    let mut nes = boot(&joypad_rom());
+    nes.mmu.ppu.fast_render = true;
+
+    for _ in 0..150 {
+        nes.step_frame().unwrap();
+    }
+
+    let samples = nes.audio_samples().len();
+    assert!((22050..=44100).contains(&samples), "{} samples kept", samples);
+}
+
+#[test]
+fn frames_are_262_lines_of_341_dots() {
+    let mut nes = boot(&joypad_rom());
+
+    nes.step_frame().unwrap();
+    let start = nes.cpu.cycle_count;
+    nes.step_frame().unwrap();
+
+    // 3 dots to a cycle, give or take the instruction running over
+    let cycles = nes.cpu.cycle_count - start;
+    assert!((29780 - 7..29781 + 8).contains(&cycles), "frame took {} cycles", cycles);
+}
+
+#[test]
+fn frames_end_even_when_sprite_dma_runs_past_vblank() {
+    // Sprite DMA over and over, each about 4 and a half lines long, so the
+    // line vblank starts on often goes by inside one step
+    let mut rom = TestRom::new();
+    let start = rom.here();
+    rom.emit(&[0xa9, 0x02, 0x8d, 0x14, 0x40])   // lda #$02; sta $4014
+        .emit(&[0x4c, start as u8, (start >> 8) as u8]);  // jmp start
+    let mut nes = boot(&rom.build());
+
+    nes.step_frame().unwrap();
+    for frame in 2..12 {
+        let start = nes.cpu.cycle_count;
+        nes.step_frame().unwrap();
+
+        let cycles = nes.cpu.cycle_count - start;
+        assert_eq!(nes.frame_count, frame);
+        assert!((29781 - 600..29781 + 600).contains(&cycles), "frame {} took {} cycles", frame, cycles);
+    }
+}
+
+#[test]
+fn regions_set_the_frame_length_and_clock() {
+    // Cycles a frame takes, and samples it makes at 44.1kHz
+    let regions = [(region::NTSC, 29781, 734), (region::PAL, 33248, 882), (region::DENDY, 35464, 882)];
+
+    for &(region, frame_cycles, frame_samples) in &regions {
+        let mut nes = boot(&joypad_rom());
+        nes.mmu.mapper.cart_mut().header.region = region;
+        nes.reset();
+
+        nes.step_frame().unwrap();
+        nes.audio_samples();
+        let start = nes.cpu.cycle_count;
+        nes.step_frame().unwrap();
+
+        let cycles = nes.cpu.cycle_count - start;
+        assert!((frame_cycles - 7..frame_cycles + 8).contains(&cycles), "region {} frame took {} cycles", region, cycles);
+        let samples = nes.audio_samples().len();
+        assert!((frame_samples - 2..frame_samples + 3).contains(&samples), "region {} made {} samples", region, samples);
+    }
+}
+
+// A headless benchmark of the whole core.  Run it in release with
+// cargo test --release --test nes -- --ignored --nocapture frame_timing
+#[test]
+#[ignore]
+fn frame_timing() {
+    const FRAMES: u32 = 600;
+
+    for &fast_render in &[true, false] {
+        let mut nes = boot(&joypad_rom());
+        nes.mmu.ppu.fast_render = fast_render;
+
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            nes.step_frame().unwrap();
+        }
+        let elapsed = start.elapsed();
+
+        let ns_per_frame = elapsed.as_nanos() / FRAMES as u128;
+        println!("{} renderer: {} frames in {:?}, {} us per frame, {} ns per scanline",
+            if fast_render {"line"} else {"dot"}, FRAMES, elapsed,
+            ns_per_frame / 1000, ns_per_frame / 262);
+    }
+}
+
+// Enables NMI and waits, with a handler that saves the P it runs with in
+// $10 and counts itself in $11
+fn nmi_rom() -> Vec<u8> {
+    let mut prg = vec![
+        0x58,                   // cli
+        0xa9, 0x80,             // lda #$80
+        0x8d, 0x00, 0x20,       // sta $2000
+        0x4c, 0x06, 0xc0,       // jmp $c006
+    ];
+    prg.resize(0x10, 0);
+    prg.extend(&[
+        0x08, 0x68, 0x85, 0x10, // php; pla; sta $10
+        0xe6, 0x11,             // inc $11
+        0x40,                   // rti
+    ]);
+    prg.resize(0x4000, 0);
+    prg[0x3ffa..].copy_from_slice(&[0x10, 0xc0, 0x00, 0xc0, 0x00, 0xc0]);
+
+    ines_image(&prg)
+}
+
+#[test]
+fn nmi_sets_the_interrupt_flag_with_either_renderer() {
+    for &fast_render in &[true, false] {
+        let mut nes = boot(&nmi_rom());
+        nes.mmu.ppu.fast_render = fast_render;
+
+        nes.step_frame().unwrap();
+        nes.step_frame().unwrap();
+        nes.step_frame().unwrap();
+
+        assert_eq!(nes.mmu.peek_u8(0x11), 2);
+        assert_eq!(nes.mmu.peek_u8(0x10) & 0x04, 0x04);
+    }
+
+    // The fast renderer's NMI takes its 7 cycles too
+    let mut nes = boot(&nmi_rom());
+    nes.mmu.ppu.fast_render = true;
+    nes.mmu.write_u8(0x2000, 0x80);
+    nes.mmu.ppu.current_scanline = 240;
+    nes.cpu.tick_count = nes.mmu.ticks_per_scanline();
+    nes.cpu.interrupt = false;
+    let start = nes.cpu.cycle_count;
+    nes.end_scanline();
+
+    assert_eq!(nes.cpu.pc, 0xc010);
+    assert!(nes.cpu.interrupt);
+    assert_eq!(nes.cpu.tick_count, 7);
+    assert_eq!(nes.cpu.cycle_count - start, 7);
+}
+
+#[test]
+fn buttons_reach_both_ports() {
+    let mut nes = boot(&joypad_rom());
+
+    nes.set_buttons(0, button::A | button::START);
+    nes.set_buttons(1, button::RIGHT);
+    nes.step_frame().unwrap();
+
+    assert_eq!(nes.mmu.peek_u8(0x12), 0x90);
+    assert_eq!(nes.mmu.peek_u8(0x13), 0x01);
+}
+
+#[test]
+fn load_rom_rejects_other_files() {
+    let mut nes = Nes::new();
+
+    assert!(nes.load_rom(b"PK\x03\x04 not a rom").is_err());
+}
+
+#[test]
+fn movies_play_back_input() {
+    let movie = Movie::parse_fm2("version 3\nport0 1\n|0|.......A|R.......||\n|0|...US...|........||\n").unwrap();
+    let mut nes = boot(&joypad_rom());
+
+    assert!(movie.play_frame(&mut nes));
+    nes.step_frame().unwrap();
+    assert_eq!(nes.mmu.peek_u8(0x12), 0x80);
+    assert_eq!(nes.mmu.peek_u8(0x13), 0x01);
+
+    assert!(movie.play_frame(&mut nes));
+    nes.step_frame().unwrap();
+    assert_eq!(nes.mmu.peek_u8(0x12), 0x18);
+
+    assert!(!movie.play_frame(&mut nes));
+    assert!(Movie::parse_fm2("|x|........|||").is_err());
+}
+
+#[test]
+fn save_ram_is_written_even_if_the_screenshot_is_not() {
+    let mut image = ines_image(&[]);
+    image[6] |= 0x02;
+    let mut nes = boot(&image);
+
+    let save_path = env::temp_dir().join("rustynes-shut-down.sav");
+    let _ = fs::remove_file(&save_path);
+    nes.mmu.save_ram_file_name = String::from(save_path.to_str().unwrap());
+    nes.mmu.write_u8(0x6000, 0x42);
+
+    let screenshot = String::from(env::temp_dir().join("rustynes-no-such-dir").join("screen.ppm").to_str().unwrap());
+    assert!(nes.shut_down(Some(&screenshot)).is_err());
+
+    let mut save = Vec::new();
+    File::open(&save_path).unwrap().read_to_end(&mut save).unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!(save[0], 0x42);
+}
+
+#[test]
+fn palettes_are_read_as_rgb() {
+    let mut data = vec![0; 192];
+    data[3..6].copy_from_slice(&[0x12, 0x34, 0x56]);
+
+    let palette = parse_palette(&data).unwrap();
+    assert_eq!(palette[1], 0x123456);
+    assert!(parse_palette(&data[..191]).is_err());
+}
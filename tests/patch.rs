@@ -0,0 +1,188 @@
+extern crate rustynes;
+extern crate crc32fast;
+
+mod common;
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+
+use rustynes::patch;
+use rustynes::cart::load_cart;
+use rustynes::mmu::Mmu;
+
+// UPS and BPS number encoding
+fn number(n: usize) -> Vec<u8> {
+    let mut n = n;
+    let mut out = Vec::new();
+
+    loop {
+        let x = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(0x80 | x);
+            return out;
+        }
+        out.push(x);
+        n -= 1;
+    }
+}
+
+fn push_crc(out: &mut Vec<u8>, crc: u32) {
+    out.extend(&[crc as u8, (crc >> 8) as u8, (crc >> 16) as u8, (crc >> 24) as u8]);
+}
+
+fn with_footer(body: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = body;
+    push_crc(&mut patch, crc32fast::hash(source));
+    push_crc(&mut patch, crc32fast::hash(target));
+    let crc = crc32fast::hash(&patch);
+    push_crc(&mut patch, crc);
+    patch
+}
+
+#[test]
+fn ips_writes_records_and_runs() {
+    let mut ips = b"PATCH".to_vec();
+    ips.extend(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);     // 2 bytes at 1
+    ips.extend(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xcc]); // 3 x $cc at 6
+    ips.extend(b"EOF");
+
+    assert_eq!(patch::apply(&ips, &[0; 8]).unwrap(), vec![0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc]);
+
+    // A size after EOF truncates the ROM
+    ips.extend(&[0x00, 0x00, 0x04]);
+    assert_eq!(patch::apply(&ips, &[0; 8]).unwrap(), vec![0, 0xaa, 0xbb, 0]);
+
+    let truncated = &ips[..10];
+    assert!(patch::apply(truncated, &[0; 8]).is_err());
+}
+
+#[test]
+fn ups_xors_in_changes() {
+    let source = [1, 2, 3, 4, 5];
+    let target = [1, 9, 3, 4, 5, 6];
+
+    let mut body = b"UPS1".to_vec();
+    body.extend(number(5));
+    body.extend(number(6));
+    body.extend(number(1));
+    body.extend(&[2 ^ 9, 0]);
+    body.extend(number(2));
+    body.extend(&[6, 0]);
+    let ups = with_footer(body, &source, &target);
+
+    assert_eq!(patch::apply(&ups, &source).unwrap(), target.to_vec());
+
+    let err = patch::apply(&ups, &[1, 2, 3, 4, 6]).unwrap_err();
+    assert!(err.to_string().contains("the patch expects"), "{}", err);
+
+    let mut corrupt = ups.clone();
+    corrupt[6] ^= 1;
+    let err = patch::apply(&corrupt, &source).unwrap_err();
+    assert!(err.to_string().contains("UPS patch is corrupt"), "{}", err);
+}
+
+#[test]
+fn bps_copies_from_source_patch_and_target() {
+    let source = b"abcdef";
+    let target = b"abXYdefefefe";
+
+    let mut body = b"BPS1".to_vec();
+    body.extend(number(source.len()));
+    body.extend(number(target.len()));
+    body.extend(number(0));
+    body.extend(number(1 << 2));                       // source read "ab"
+    body.extend(number((1 << 2) | 1));                 // target read "XY"
+    body.extend(b"XY");
+    body.extend(number((2 << 2) | 2));                 // source copy "def"
+    body.extend(number(3 << 1));
+    body.extend(number((4 << 2) | 3));                 // target copy "efefe", overlapping
+    body.extend(number(5 << 1));
+    let bps = with_footer(body, source, target);
+
+    assert_eq!(patch::apply(&bps, source).unwrap(), target.to_vec());
+
+    let err = patch::apply(&bps, b"abcdeg").unwrap_err();
+    assert!(err.to_string().contains("CRC32"), "{}", err);
+}
+
+#[test]
+fn oversized_numbers_are_rejected() {
+    let source = [1, 2, 3];
+
+    // Nine bytes of 7 bits, each adding the next shift too, don't fit
+    let mut body = b"UPS1".to_vec();
+    body.extend(&[0x7f; 9]);
+    body.push(0x80);
+    let ups = with_footer(body, &source, &source);
+
+    let err = patch::apply(&ups, &source).unwrap_err();
+    assert!(err.to_string().contains("number too large"), "{}", err);
+}
+
+#[test]
+fn huge_targets_are_rejected() {
+    let source = [1, 2, 3];
+
+    // Far past 64MiB, before anything would be allocated for it
+    for magic in &[b"UPS1", b"BPS1"] {
+        let mut body = magic.to_vec();
+        body.extend(number(source.len()));
+        body.extend(number(1 << 40));
+        body.extend(number(0));
+        let patch = with_footer(body, &source, &source);
+
+        let err = patch::apply(&patch, &source).unwrap_err();
+        assert!(err.to_string().contains("byte ROM from 3 bytes"), "{}", err);
+    }
+
+    // Growing a small ROM to a board's size is fine
+    let mut body = b"UPS1".to_vec();
+    body.extend(number(source.len()));
+    body.extend(number(1 << 20));
+    let target = {
+        let mut target = source.to_vec();
+        target.resize(1 << 20, 0);
+        target
+    };
+    let ups = with_footer(body, &source, &target);
+    assert_eq!(patch::apply(&ups, &source).unwrap(), target);
+
+    // A UPS gap can't carry the offset past the largest number
+    let mut body = b"UPS1".to_vec();
+    body.extend(number(source.len()));
+    body.extend(number(source.len()));
+    body.extend(number(usize::MAX));
+    body.extend(&[1, 0]);
+    let ups = with_footer(body, &source, &source);
+
+    let err = patch::apply(&ups, &source).unwrap_err();
+    assert!(err.to_string().contains("past the end"), "{}", err);
+}
+
+#[test]
+fn unknown_patches_are_rejected() {
+    assert!(patch::apply(b"not a patch", &[0; 8]).is_err());
+}
+
+#[test]
+fn patches_next_to_the_rom_are_applied() {
+    let mut prg = vec![0xea; 0x4000];
+    prg[0] = 0x00;
+    let image = common::ines_image(&prg);
+
+    let mut rom_path = env::temp_dir();
+    rom_path.push("rustynes-patched.nes");
+    File::create(&rom_path).unwrap().write_all(&image).unwrap();
+
+    // $8000 is byte 16 of the file, after the header
+    let mut ips = b"PATCH".to_vec();
+    ips.extend(&[0x00, 0x00, 0x10, 0x00, 0x01, 0x42]);
+    ips.extend(b"EOF");
+    File::create(rom_path.with_extension("ips")).unwrap().write_all(&ips).unwrap();
+
+    let mut mmu = Mmu::new();
+    load_cart(&String::from(rom_path.to_str().unwrap()), None, None, &mut mmu).unwrap();
+    assert_eq!(mmu.peek_u8(0x8000), 0x42);
+}
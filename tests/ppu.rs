@@ -0,0 +1,567 @@
+extern crate rustynes;
+
+mod common;
+
+use common::{boot, ines_image, run_blargg_rom, run_blargg_roms_in, TestRom};
+use rustynes::Nes;
+use rustynes::ppu::Ppu;
+
+fn set_address(nes: &mut Nes, address: u16) {
+    nes.mmu.write_u8(0x2006, (address >> 8) as u8);
+    nes.mmu.write_u8(0x2006, address as u8);
+}
+
+// Tile 1 is solid colour 1, and it's placed at the given name table
+// positions.  Everything else is tile 0, which is blank.
+fn setup_tiles(nes: &mut Nes, positions: &[u16]) {
+    set_address(nes, 0x0010);
+    for _ in 0..8 {
+        nes.mmu.write_u8(0x2007, 0xff);
+    }
+    for &address in positions {
+        set_address(nes, address);
+        nes.mmu.write_u8(0x2007, 1);
+    }
+    set_address(nes, 0x3f01);
+    nes.mmu.write_u8(0x2007, 0x30);
+
+    nes.mmu.write_u8(0x2001, 0x0a);
+}
+
+// Boots with the chosen renderer
+fn boot_with(fast_render: bool) -> Nes {
+    let mut nes = boot(&ines_image(&[]));
+    nes.mmu.ppu.fast_render = fast_render;
+    nes
+}
+
+// Runs from the pre-render line up to the given dot.  The fast renderer
+// only stops at the start of a line.
+fn render_to(nes: &mut Nes, line: usize, dot: usize) {
+    nes.mmu.ppu.current_scanline = 261;
+    nes.mmu.ppu.dot = 0;
+    render_on_to(nes, line, dot);
+}
+
+// Carries on from where it was
+fn render_on_to(nes: &mut Nes, line: usize, dot: usize) {
+    while nes.mmu.ppu.current_scanline != line || nes.mmu.ppu.dot != dot {
+        if nes.mmu.ppu.fast_render {
+            nes.mmu.ppu.render_scanline(&mut *nes.mmu.mapper);
+        }
+        else {
+            nes.mmu.ppu.tick(&mut *nes.mmu.mapper);
+        }
+    }
+}
+
+fn lit(nes: &Nes, x: usize, y: usize) -> bool {
+    nes.mmu.ppu.offscreen_buffer[y * 256 + x] == nes.mmu.ppu.palette[0x30]
+}
+
+#[test]
+fn scroll_writes_share_the_address_toggle() {
+    let mut nes = boot(&ines_image(&[]));
+    set_address(&mut nes, 0x2400);
+    nes.mmu.write_u8(0x2007, 0x55);
+
+    // A half-finished $2006 write is forgotten once $2002 is read
+    nes.mmu.write_u8(0x2006, 0x3f);
+    nes.mmu.read_u8(0x2002);
+    set_address(&mut nes, 0x2400);
+    nes.mmu.read_u8(0x2007);
+    assert_eq!(nes.mmu.read_u8(0x2007), 0x55);
+
+    // A $2005 write counts as the first half, so the $2006 write after it
+    // is taken as the low byte
+    nes.mmu.write_u8(0x2005, 0x00);
+    nes.mmu.write_u8(0x2006, 0x00);
+    nes.mmu.read_u8(0x2007);
+    assert_eq!(nes.mmu.read_u8(0x2007), 0x55);
+}
+
+#[test]
+fn scrolling_moves_the_background() {
+    for &fast_render in &[true, false] {
+        let mut nes = boot_with(fast_render);
+        // Column 2 of row 1, and column 0 of row 0 in the name table to the right
+        setup_tiles(&mut nes, &[0x2022, 0x2400]);
+
+        // 12 across and 6 down puts the first tile at (4, 2) and the second
+        // at (244, -6)
+        nes.mmu.read_u8(0x2002);
+        nes.mmu.write_u8(0x2000, 0);
+        nes.mmu.write_u8(0x2005, 12);
+        nes.mmu.write_u8(0x2005, 6);
+        render_to(&mut nes, 10, 0);
+
+        assert!(!lit(&nes, 3, 2) && lit(&nes, 4, 2) && lit(&nes, 11, 2) && !lit(&nes, 12, 2));
+        assert!(!lit(&nes, 4, 1) && lit(&nes, 4, 9));
+        assert!(lit(&nes, 244, 0) && lit(&nes, 251, 1) && !lit(&nes, 243, 0) && !lit(&nes, 244, 2));
+    }
+}
+
+#[test]
+fn address_writes_mid_frame_split_the_screen() {
+    for &fast_render in &[true, false] {
+        let mut nes = boot_with(fast_render);
+        setup_tiles(&mut nes, &[0x2000, 0x2800]);
+
+        nes.mmu.read_u8(0x2002);
+        nes.mmu.write_u8(0x2000, 0);
+        nes.mmu.write_u8(0x2005, 0);
+        nes.mmu.write_u8(0x2005, 0);
+
+        // The fast renderer draws a line after the CPU has run it, so a
+        // write during line 100 counts from its start.  Dot by dot, it has
+        // to be in the hblank before, ahead of the first tile fetches.
+        if fast_render {
+            render_to(&mut nes, 100, 0);
+        }
+        else {
+            render_to(&mut nes, 99, 320);
+        }
+        assert!(lit(&nes, 0, 0) && !lit(&nes, 0, 8));
+
+        // Point at the top of the bottom name table from line 100, the way
+        // a status bar is drawn.  $2800 would be read as fine Y 2, as the
+        // top bits of the address are where fine Y goes.
+        set_address(&mut nes, 0x0800);
+        render_on_to(&mut nes, 109, 0);
+        assert!(!lit(&nes, 0, 99) && lit(&nes, 0, 100) && lit(&nes, 0, 107) && !lit(&nes, 0, 108));
+    }
+}
+
+#[test]
+fn dot_renderer_draws_what_the_fast_one_does() {
+    // 8x8 sprites, then 8x16
+    for &control in &[0x00, 0x20] {
+        let frames : Vec<Vec<u32>> = [true, false].iter().map(|&fast_render| {
+            let mut nes = boot_with(fast_render);
+            setup_tiles(&mut nes, &[0x2000, 0x2043, 0x21e5, 0x2401]);
+
+            // Tile 2 is a triangle in colour 2, to show up flipping, and
+            // tile 3 below it a bar across its top in colour 1
+            set_address(&mut nes, 0x0020);
+            for _ in 0..8 {
+                nes.mmu.write_u8(0x2007, 0);
+            }
+            for row in 0..8 {
+                nes.mmu.write_u8(0x2007, 0xff << (7 - row));
+            }
+            for row in 0..16 {
+                nes.mmu.write_u8(0x2007, if row < 2 {0xff} else {0});
+            }
+            set_address(&mut nes, 0x3f11);
+            nes.mmu.write_u8(0x2007, 0x2a);
+            nes.mmu.write_u8(0x2007, 0x16);
+
+            // Y, tile, attributes and X: plain, flipped both ways, off the
+            // right edge and behind a background tile
+            let sprites = [20, 2, 0x00, 30, 40, 2, 0xc0, 60, 60, 2, 0x40, 252, 8, 2, 0x20, 24];
+            nes.mmu.ppu.sprite_ram[..16].copy_from_slice(&sprites);
+
+            nes.mmu.read_u8(0x2002);
+            nes.mmu.write_u8(0x2000, control);
+            nes.mmu.write_u8(0x2005, 5);
+            nes.mmu.write_u8(0x2005, 3);
+            nes.mmu.write_u8(0x2001, 0x1e);
+            render_to(&mut nes, 240, 0);
+
+            nes.mmu.ppu.offscreen_buffer.clone()
+        }).collect();
+
+        // The first row of the first sprite is its tip
+        let red = Ppu::new().palette[0x16];
+        assert!(frames[1][21 * 256 + 30] == red && frames[1][21 * 256 + 31] != red);
+
+        // Flipped, the bar at the top of tile 3 is rows 6 and 7 of the
+        // 8x16 sprite
+        if control == 0x20 {
+            let green = Ppu::new().palette[0x2a];
+            assert!(frames[1][47 * 256 + 60] == green && frames[1][48 * 256 + 60] == green);
+            assert!(frames[1][46 * 256 + 60] != green && frames[1][49 * 256 + 60] != green);
+        }
+
+        for y in 0..240 {
+            assert_eq!(&frames[0][y * 256..(y + 1) * 256], &frames[1][y * 256..(y + 1) * 256],
+                "line {} differs with control {:02x}", y, control);
+        }
+    }
+}
+
+// Tiles as setup_tiles, then the sprites given in sprite_ram and the
+// rest off screen
+fn setup_sprites(nes: &mut Nes, positions: &[u16], sprites: &[u8]) {
+    setup_tiles(nes, positions);
+    set_address(nes, 0x3f11);
+    nes.mmu.write_u8(0x2007, 0x30);
+
+    for byte in nes.mmu.ppu.sprite_ram.iter_mut() {
+        *byte = 0xf0;
+    }
+    nes.mmu.ppu.sprite_ram[..sprites.len()].copy_from_slice(sprites);
+
+    nes.mmu.write_u8(0x2001, 0x1e);
+}
+
+fn overflow_flag(nes: &mut Nes) -> bool {
+    (nes.mmu.read_u8(0x2002) & 0x20) != 0
+}
+
+#[test]
+fn sprite_size_can_change_between_evaluation_and_fetch() {
+    // A flipped 8x16 sprite from line 21, switched to 8x8 just after it's
+    // found for line 31.  It's fetched as row 2 of an 8x8 sprite rather
+    // than row 10 of a 16 high one.
+    let mut nes = boot_with(false);
+    setup_sprites(&mut nes, &[], &[20, 1, 0x80, 40]);
+    nes.mmu.write_u8(0x2000, 0x20);
+    render_to(&mut nes, 30, 258);
+    nes.mmu.write_u8(0x2000, 0x00);
+    render_on_to(&mut nes, 240, 0);
+
+    assert!(lit(&nes, 40, 31) && !lit(&nes, 40, 32));
+}
+
+#[test]
+fn eight_sprites_fit_on_a_line() {
+    // Nine sprites in a row along line 51
+    let sprites : Vec<u8> = (0..9).flat_map(|i| vec![50, 1, 0, i * 10]).collect();
+
+    for &fast_render in &[true, false] {
+        let mut nes = boot_with(fast_render);
+        setup_sprites(&mut nes, &[], &sprites);
+        render_to(&mut nes, 60, 0);
+
+        assert!(lit(&nes, 70, 51) && !lit(&nes, 80, 51));
+        assert!(overflow_flag(&mut nes));
+
+        // Without the limit the ninth is drawn, and the flag is the same
+        let mut nes = boot_with(fast_render);
+        nes.mmu.ppu.no_sprite_limit = true;
+        setup_sprites(&mut nes, &[], &sprites);
+        render_to(&mut nes, 60, 0);
+
+        assert!(lit(&nes, 70, 51) && lit(&nes, 80, 51));
+        assert!(overflow_flag(&mut nes));
+
+        // Cleared for the next frame
+        render_on_to(&mut nes, 0, 0);
+        assert!(!overflow_flag(&mut nes));
+    }
+}
+
+#[test]
+fn sprite_overflow_is_found_the_buggy_way() {
+    let eight : Vec<u8> = (0..8).flat_map(|i| vec![50, 1, 0, i * 10]).collect();
+
+    for &fast_render in &[true, false] {
+        // After the eighth, the search moves on a byte as well as a sprite
+        // each time it misses.  Sprite 9 is on the line but its tile is
+        // what's read as Y, so it's missed.
+        let mut sprites = eight.clone();
+        sprites.extend_from_slice(&[200, 0xf0, 0xf0, 0xf0, 50, 200, 0, 90]);
+
+        let mut nes = boot_with(fast_render);
+        setup_sprites(&mut nes, &[], &sprites);
+        render_to(&mut nes, 60, 0);
+        assert!(!overflow_flag(&mut nes));
+
+        // Whereas here only 8 are on the line, but a tile number is in range
+        let mut sprites = eight.clone();
+        sprites.extend_from_slice(&[200, 0xf0, 0xf0, 0xf0, 200, 50, 0, 90]);
+
+        let mut nes = boot_with(fast_render);
+        setup_sprites(&mut nes, &[], &sprites);
+        render_to(&mut nes, 60, 0);
+        assert!(overflow_flag(&mut nes));
+    }
+}
+
+fn sprite_0_hit(nes: &mut Nes) -> bool {
+    (nes.mmu.read_u8(0x2002) & 0x40) != 0
+}
+
+// Sprite 0 at the given X on lines 51 to 58, over a row of background
+// tiles on lines 48 to 55
+fn sprite_0_over_background(fast_render: bool, x: u8, mask: u8) -> Nes {
+    let row : Vec<u16> = (0x20c0..0x20e0).collect();
+    let mut nes = boot_with(fast_render);
+    setup_sprites(&mut nes, &row, &[50, 1, 0, x]);
+    nes.mmu.write_u8(0x2001, mask);
+
+    nes.mmu.read_u8(0x2002);
+    nes.mmu.write_u8(0x2000, 0);
+    nes.mmu.write_u8(0x2005, 0);
+    nes.mmu.write_u8(0x2005, 0);
+    nes
+}
+
+#[test]
+fn sprite_0_hits_where_it_overlaps_the_background() {
+    for &fast_render in &[true, false] {
+        let mut nes = sprite_0_over_background(fast_render, 100, 0x1e);
+        render_to(&mut nes, 51, 0);
+        assert!(!sprite_0_hit(&mut nes));
+        render_on_to(&mut nes, 52, 0);
+        assert!(sprite_0_hit(&mut nes));
+
+        // Reading doesn't clear it, the pre-render line does
+        render_on_to(&mut nes, 261, 0);
+        assert!(sprite_0_hit(&mut nes));
+        render_on_to(&mut nes, 0, 0);
+        assert!(!sprite_0_hit(&mut nes));
+
+        // Below the background there's nothing to hit
+        let mut nes = sprite_0_over_background(fast_render, 100, 0x1e);
+        nes.mmu.ppu.sprite_ram[0] = 100;
+        render_to(&mut nes, 240, 0);
+        assert!(!sprite_0_hit(&mut nes));
+    }
+}
+
+#[test]
+fn sprite_0_hit_misses_the_edges() {
+    for &fast_render in &[true, false] {
+        let hits = |x: u8, mask: u8| {
+            let mut nes = sprite_0_over_background(fast_render, x, mask);
+            render_to(&mut nes, 240, 0);
+            sprite_0_hit(&mut nes)
+        };
+
+        // At 255 only its first pixel is on screen, and that can't hit
+        assert!(hits(254, 0x1e));
+        assert!(!hits(255, 0x1e));
+
+        // Clipping either layer in the left 8 pixels stops hits there
+        assert!(hits(0, 0x1e));
+        assert!(!hits(0, 0x1c));
+        assert!(!hits(0, 0x1a));
+        assert!(hits(1, 0x1c));
+
+        // Both layers have to be on
+        assert!(!hits(100, 0x16));
+        assert!(!hits(100, 0x0e));
+    }
+}
+
+#[test]
+fn sprite_0_hit_is_seen_from_the_dot_it_happens() {
+    // Pixel 100 of line 51 is drawn on dot 101
+    let mut nes = sprite_0_over_background(false, 100, 0x1e);
+    render_to(&mut nes, 51, 101);
+    assert!(!sprite_0_hit(&mut nes));
+    render_on_to(&mut nes, 51, 102);
+    assert!(sprite_0_hit(&mut nes));
+
+    // And cleared on dot 1 of the pre-render line
+    render_on_to(&mut nes, 261, 1);
+    assert!(sprite_0_hit(&mut nes));
+    render_on_to(&mut nes, 261, 2);
+    assert!(!sprite_0_hit(&mut nes));
+}
+
+// blargg's sprite 0 hit tests aren't redistributed with the crate.  Copy
+// the singles that report through $6000 into tests/roms/sprite_hit and
+// run with `cargo test -- --ignored`.  The ROMs built below cover the
+// main rules in the meantime.
+#[test]
+#[ignore]
+fn sprite_hit_roms() {
+    let failures = run_blargg_roms_in("tests/roms/sprite_hit", 60 * 60);
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// Branches for TestRom::expect on bit 6 of $2002, read with BIT
+const BVC : u8 = 0x50;
+const BVS : u8 = 0x70;
+
+// bit $2002; bpl -5
+fn wait_for_vblank(rom: &mut TestRom) {
+    rom.emit(&[0x2c, 0x02, 0x20, 0x10, 0xfb]);
+}
+
+// Loops for 2 + count * (5 * inner + 6) - 1 cycles
+fn delay(rom: &mut TestRom, count: u8, inner: u8) {
+    rom.emit(&[0xa0, count, 0xa2, inner])       // ldy #count; ldx #inner
+        .emit(&[0xca, 0xd0, 0xfd])              // dex; bne -3
+        .emit(&[0x88, 0xd0, 0xf8]);             // dey; bne -8
+}
+
+// In the style of blargg's sprite_hit singles, but built here so they run
+// by default.  Like sprite_0_over_background, sprite 0 is tile 1 on lines
+// 51 to 58 over a row of tile 1 in the background, all set up through the
+// registers in vblank.  The mask is written to $2001 at the start of a
+// vblank, so the next frame is the first drawn.
+fn sprite_0_hit_rom(x: u8, mask: u8) -> TestRom {
+    let mut rom = TestRom::new();
+    wait_for_vblank(&mut rom);
+
+    rom.emit(&[0xa9, 0x00, 0x8d, 0x06, 0x20, 0xa9, 0x10, 0x8d, 0x06, 0x20])  // $2006 = $0010
+        .emit(&[0xa9, 0xff, 0xa2, 0x08])                                    // lda #$ff; ldx #8
+        .emit(&[0x8d, 0x07, 0x20, 0xca, 0xd0, 0xfa])                        // sta $2007; dex; bne -6
+        .emit(&[0xa9, 0x20, 0x8d, 0x06, 0x20, 0xa9, 0xc0, 0x8d, 0x06, 0x20])  // $2006 = $20c0
+        .emit(&[0xa9, 0x01, 0xa2, 0x20])                                    // lda #1; ldx #32
+        .emit(&[0x8d, 0x07, 0x20, 0xca, 0xd0, 0xfa])                        // sta $2007; dex; bne -6
+        .emit(&[0xa9, 0x00, 0x8d, 0x03, 0x20])                              // lda #0; sta $2003
+        .emit(&[0xa9, 50, 0x8d, 0x04, 0x20, 0xa9, 0x01, 0x8d, 0x04, 0x20])   // Y 50, tile 1
+        .emit(&[0xa9, 0x00, 0x8d, 0x04, 0x20, 0xa9, x, 0x8d, 0x04, 0x20])    // attributes 0, X
+        .emit(&[0xa9, 0x00, 0x8d, 0x00, 0x20, 0x8d, 0x05, 0x20, 0x8d, 0x05, 0x20]);  // $2000, scroll 0
+
+    wait_for_vblank(&mut rom);
+    rom.emit(&[0xa9, mask, 0x8d, 0x01, 0x20]);  // lda #mask; sta $2001
+    rom
+}
+
+#[test]
+fn sprite_0_hit_test_roms() {
+    let cases = [
+        (100, 0x1e, true, "No hit where sprite 0 overlaps the background"),
+        (254, 0x1e, true, "No hit at X 254"),
+        (255, 0x1e, false, "Hit at X 255"),
+        (0, 0x1e, true, "No hit at X 0"),
+        (0, 0x1c, false, "Hit with the background clipped"),
+        (0, 0x1a, false, "Hit with sprites clipped"),
+        (1, 0x1c, true, "No hit 7 pixels into the clipped background"),
+        (100, 0x16, false, "Hit with the background off"),
+        (100, 0x0e, false, "Hit with sprites off")
+    ];
+
+    for &(x, mask, hits, message) in &cases {
+        let mut rom = sprite_0_hit_rom(x, mask);
+        rom.emit(&[0x2c, 0x02, 0x20])           // bit $2002
+            .expect(BVC, "Hit before the frame was drawn");
+        wait_for_vblank(&mut rom);
+        rom.emit(&[0x2c, 0x02, 0x20])
+            .expect(if hits {BVS} else {BVC}, message);
+
+        assert_eq!(run_blargg_rom(&rom.build(), 10), Ok(String::from("Passed")),
+            "sprite 0 at X {} with $2001 = {:02x}", x, mask);
+    }
+}
+
+#[test]
+fn sprite_0_hit_timing_test_rom() {
+    let mut rom = sprite_0_hit_rom(100, 0x1e);
+
+    // Set by the first frame, and still set through vblank
+    wait_for_vblank(&mut rom);
+    rom.emit(&[0x2c, 0x02, 0x20])
+        .expect(BVS, "No hit");
+
+    // Cleared on the pre-render line.  Pixel 100 of line 51 is 52 lines
+    // and 101 dots on from there, about 5944 cycles.
+    rom.emit(&[0x2c, 0x02, 0x20, 0x70, 0xfb]);  // bit $2002; bvs -5
+    delay(&mut rom, 8, 145);                    // 5849 cycles
+    rom.emit(&[0x2c, 0x02, 0x20])
+        .expect(BVC, "Hit too soon");
+    delay(&mut rom, 1, 37);                     // 192 cycles
+    rom.emit(&[0x2c, 0x02, 0x20])
+        .expect(BVS, "Hit too late");
+
+    assert_eq!(run_blargg_rom(&rom.build(), 10), Ok(String::from("Passed")));
+}
+
+fn vblank_flag(nes: &mut Nes) -> bool {
+    (nes.mmu.read_u8(0x2002) & 0x80) != 0
+}
+
+#[test]
+fn vblank_is_set_until_read_or_the_pre_render_line() {
+    for &fast_render in &[true, false] {
+        let mut nes = boot_with(fast_render);
+        render_to(&mut nes, 240, 0);
+        assert!(!vblank_flag(&mut nes));
+        render_on_to(&mut nes, 242, 0);
+        assert!(vblank_flag(&mut nes));
+        assert!(!vblank_flag(&mut nes));
+
+        render_on_to(&mut nes, 242, 0);
+        render_on_to(&mut nes, 0, 0);
+        assert!(!vblank_flag(&mut nes));
+    }
+}
+
+#[test]
+fn frames_are_262_lines_and_odd_ones_a_dot_short() {
+    let mut nes = boot_with(true);
+    let mut lines = 0;
+    render_to(&mut nes, 0, 0);
+    loop {
+        nes.mmu.ppu.render_scanline(&mut *nes.mmu.mapper);
+        lines += 1;
+        if nes.mmu.ppu.current_scanline == 0 {
+            break;
+        }
+    }
+    assert_eq!(lines, 262);
+
+    // Dot by dot, the skip only happens while rendering
+    for &mask in &[0x00, 0x08] {
+        let mut nes = boot_with(false);
+        nes.mmu.write_u8(0x2001, mask);
+        render_to(&mut nes, 0, 0);
+
+        let frame_dots = |nes: &mut Nes| {
+            let mut dots = 0;
+            loop {
+                nes.mmu.ppu.tick(&mut *nes.mmu.mapper);
+                dots += 1;
+                if nes.mmu.ppu.current_scanline == 0 && nes.mmu.ppu.dot == 0 {
+                    return dots;
+                }
+            }
+        };
+        let first = frame_dots(&mut nes);
+        let second = frame_dots(&mut nes);
+        if mask == 0 {
+            assert_eq!((first, second), (262 * 341, 262 * 341));
+        }
+        else {
+            assert_eq!(first + second, 2 * 262 * 341 - 1);
+        }
+    }
+}
+
+#[test]
+fn reading_status_as_vblank_starts_stops_the_nmi() {
+    // Where the read lands, what it sees, and whether NMI follows
+    for &(dot, seen, nmi) in &[(0, false, false), (1, true, false), (2, true, false), (3, true, true)] {
+        let mut nes = boot_with(false);
+        nes.mmu.write_u8(0x2000, 0x80);
+        render_to(&mut nes, 241, dot);
+
+        assert_eq!(vblank_flag(&mut nes), seen, "read on dot {}", dot);
+        render_on_to(&mut nes, 242, 0);
+        assert_eq!(nes.mmu.ppu.take_nmi(), nmi, "read on dot {}", dot);
+        assert!(!vblank_flag(&mut nes));
+    }
+}
+
+#[test]
+fn enabling_nmi_in_vblank_raises_it() {
+    for &fast_render in &[true, false] {
+        let mut nes = boot_with(fast_render);
+        render_to(&mut nes, 100, 0);
+        nes.mmu.write_u8(0x2000, 0x80);
+        assert!(!nes.mmu.ppu.take_nmi());
+
+        // Each time it's turned on, but not while it stays on
+        nes.mmu.write_u8(0x2000, 0x00);
+        render_on_to(&mut nes, 250, 0);
+        nes.mmu.write_u8(0x2000, 0x80);
+        assert!(nes.mmu.ppu.take_nmi());
+        nes.mmu.write_u8(0x2000, 0x80);
+        assert!(!nes.mmu.ppu.take_nmi());
+        nes.mmu.write_u8(0x2000, 0x00);
+        nes.mmu.write_u8(0x2000, 0x80);
+        assert!(nes.mmu.ppu.take_nmi());
+
+        // Not once the flag has been read
+        nes.mmu.read_u8(0x2002);
+        nes.mmu.write_u8(0x2000, 0x00);
+        nes.mmu.write_u8(0x2000, 0x80);
+        assert!(!nes.mmu.ppu.take_nmi());
+    }
+}